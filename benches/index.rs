@@ -0,0 +1,73 @@
+#![feature(test)]
+
+extern crate test;
+
+use pinoedino::db::{Db, Limits, Store};
+use pinoedino::engine::{Amount, Currency, Transaction};
+use test::Bencher;
+
+const TXS: u32 = 100_000;
+
+fn deposit() -> Transaction {
+    Transaction::new_deposit(Amount::parse(Currency::Usd, "1").unwrap())
+}
+
+// Ids spread over the whole u32 range
+fn scattered(id: u32) -> u32 {
+    id.wrapping_mul(2_654_435_761)
+}
+
+fn filled_db(limits: Limits, ids: impl Fn(u32) -> u32) -> Db {
+    let mut db = Db::new(limits);
+    for id in (0..TXS).map(ids) {
+        db.add_id(id);
+        db.add_tx(id, deposit(), 1);
+    }
+    db
+}
+
+#[bench]
+fn add_txs(b: &mut Bencher) {
+    b.iter(|| filled_db(Limits::default(), |id| id));
+}
+
+#[bench]
+fn add_txs_with_max_memory(b: &mut Bencher) {
+    let limits = Limits {
+        retention: None,
+        max_memory: Some(1024 * 1024),
+    };
+    b.iter(|| filled_db(limits, |id| id));
+}
+
+#[bench]
+fn get_txs(b: &mut Bencher) {
+    let db = filled_db(Limits::default(), |id| id);
+    b.iter(|| (0..TXS).filter(|id| db.get_tx(*id).is_some()).count());
+}
+
+#[bench]
+fn has_ids(b: &mut Bencher) {
+    let db = filled_db(Limits::default(), |id| id);
+    b.iter(|| (0..TXS).filter(|id| db.has_id(*id)).count());
+}
+
+#[bench]
+fn add_scattered_txs(b: &mut Bencher) {
+    b.iter(|| filled_db(Limits::default(), scattered));
+}
+
+#[bench]
+fn add_scattered_txs_with_max_memory(b: &mut Bencher) {
+    let limits = Limits {
+        retention: None,
+        max_memory: Some(1024 * 1024),
+    };
+    b.iter(|| filled_db(limits, scattered));
+}
+
+#[bench]
+fn get_scattered_txs(b: &mut Bencher) {
+    let db = filled_db(Limits::default(), scattered);
+    b.iter(|| (0..TXS).filter(|id| db.get_tx(scattered(*id)).is_some()).count());
+}
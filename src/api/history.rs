@@ -0,0 +1,45 @@
+use super::{Operation, Outcome};
+use crate::engine::Balance;
+use std::collections::HashMap;
+
+/// A row of a client that reached the engine, applied or not
+#[derive(Debug, Clone)]
+pub struct Step {
+    pub tx: u32,
+    /// None if the row is not a valid operation
+    pub operation: Option<Operation>,
+    pub outcome: Outcome,
+    /// Balance of the currency of the operation after the step, None if the currency is unknown
+    /// or the client has no balance in it
+    pub balance: Option<Balance>,
+    pub locked: bool,
+}
+
+/// Ordered steps of every client, or only of the client given to new
+pub struct History {
+    client: Option<u16>,
+    steps: HashMap<u16, Vec<Step>>,
+}
+
+impl History {
+    pub fn new(client: Option<u16>) -> Self {
+        History {
+            client,
+            steps: HashMap::new(),
+        }
+    }
+
+    pub fn is_kept(&self, client: u16) -> bool {
+        self.client.map_or(true, |kept| kept == client)
+    }
+
+    pub fn push(&mut self, client: u16, step: Step) {
+        if self.is_kept(client) {
+            self.steps.entry(client).or_insert_with(Vec::new).push(step)
+        }
+    }
+
+    pub fn of(&self, client: u16) -> &[Step] {
+        self.steps.get(&client).map_or(&[], |steps| &steps[..])
+    }
+}
@@ -0,0 +1,556 @@
+use crate::db::{Db, Limits, Snapshot, Store, Transaction as StoredTx};
+use crate::engine::{
+    Amount, Client, Currency, DisputePolicy, DuplicatePolicy, FrozenPolicy, RejectReason,
+    Transaction,
+};
+
+mod history;
+#[cfg(test)]
+mod test;
+
+pub use history::{History, Step};
+
+/// A typed transaction, deposit and withdrawal amounts are positive
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operation {
+    Deposit {
+        client: u16,
+        tx: u32,
+        amount: Amount,
+    },
+    Withdrawal {
+        client: u16,
+        tx: u32,
+        amount: Amount,
+    },
+    /// Dispute amount of tx, what has not been disputed yet if None. A row with a key is
+    /// applied once, the key is reserved as a tx id
+    Dispute {
+        client: u16,
+        tx: u32,
+        amount: Option<Amount>,
+        key: Option<u32>,
+    },
+    /// Resolve amount of the disputed part of tx, all of it if None
+    Resolve {
+        client: u16,
+        tx: u32,
+        amount: Option<Amount>,
+        key: Option<u32>,
+    },
+    /// Charge back amount of the disputed part of tx, all of it if None
+    Chargeback {
+        client: u16,
+        tx: u32,
+        amount: Option<Amount>,
+        key: Option<u32>,
+    },
+    /// Withdraw amount from client and deposit it to to_client, both or none
+    Transfer {
+        client: u16,
+        to_client: u16,
+        tx: u32,
+        amount: Amount,
+    },
+    /// Lock the account until an Unfreeze
+    Freeze {
+        client: u16,
+        tx: u32,
+        reason: String,
+    },
+    Unfreeze {
+        client: u16,
+        tx: u32,
+        reason: String,
+    },
+    /// Lock the account forever
+    Close {
+        client: u16,
+        tx: u32,
+        reason: String,
+    },
+}
+
+impl Operation {
+    pub fn client(&self) -> u16 {
+        match self {
+            Self::Deposit { client, .. }
+            | Self::Withdrawal { client, .. }
+            | Self::Dispute { client, .. }
+            | Self::Resolve { client, .. }
+            | Self::Chargeback { client, .. }
+            | Self::Transfer { client, .. }
+            | Self::Freeze { client, .. }
+            | Self::Unfreeze { client, .. }
+            | Self::Close { client, .. } => *client,
+        }
+    }
+
+    /// Type of the row in the input
+    pub fn kind(&self) -> &'static str {
+        match self {
+            Self::Deposit { .. } => "deposit",
+            Self::Withdrawal { .. } => "withdraw",
+            Self::Dispute { .. } => "dispute",
+            Self::Resolve { .. } => "resolve",
+            Self::Chargeback { .. } => "chargeback",
+            Self::Transfer { .. } => "transfer",
+            Self::Freeze { .. } => "freeze",
+            Self::Unfreeze { .. } => "unfreeze",
+            Self::Close { .. } => "close",
+        }
+    }
+
+    /// Amount of deposits, withdrawals and partial disputes, resolves and chargebacks
+    pub fn amount(&self) -> Option<Amount> {
+        match self {
+            Self::Deposit { amount, .. }
+            | Self::Withdrawal { amount, .. }
+            | Self::Transfer { amount, .. } => Some(*amount),
+            Self::Dispute { amount, .. }
+            | Self::Resolve { amount, .. }
+            | Self::Chargeback { amount, .. } => *amount,
+            _ => None,
+        }
+    }
+
+    /// Audit reason of admin operations
+    pub fn reason(&self) -> Option<&str> {
+        match self {
+            Self::Freeze { reason, .. }
+            | Self::Unfreeze { reason, .. }
+            | Self::Close { reason, .. } => Some(reason),
+            _ => None,
+        }
+    }
+
+    pub fn tx(&self) -> u32 {
+        match self {
+            Self::Deposit { tx, .. }
+            | Self::Withdrawal { tx, .. }
+            | Self::Dispute { tx, .. }
+            | Self::Resolve { tx, .. }
+            | Self::Chargeback { tx, .. }
+            | Self::Transfer { tx, .. }
+            | Self::Freeze { tx, .. }
+            | Self::Unfreeze { tx, .. }
+            | Self::Close { tx, .. } => *tx,
+        }
+    }
+}
+
+/// Ok if the operation has been applied
+pub type Outcome = Result<(), RejectReason>;
+
+/// Apply operations to the clients, the only entry point to the store
+pub struct Engine<S: Store = Db> {
+    db: S,
+    policy: DisputePolicy,
+    duplicates: DuplicatePolicy,
+    frozen: FrozenPolicy,
+    history: Option<History>,
+}
+
+impl Engine<Db> {
+    /// In memory engine, see Limits
+    pub fn new(policy: DisputePolicy, limits: Limits) -> Self {
+        Engine::with_store(Db::new(limits), policy)
+    }
+}
+
+impl<S: Store> Engine<S> {
+    pub fn with_store(store: S, policy: DisputePolicy) -> Self {
+        Engine {
+            db: store,
+            policy,
+            duplicates: DuplicatePolicy::default(),
+            frozen: FrozenPolicy::default(),
+            history: None,
+        }
+    }
+
+    pub fn set_duplicates(&mut self, duplicates: DuplicatePolicy) {
+        self.duplicates = duplicates;
+    }
+
+    pub fn duplicates(&self) -> DuplicatePolicy {
+        self.duplicates
+    }
+
+    pub fn set_frozen(&mut self, frozen: FrozenPolicy) {
+        self.frozen = frozen;
+    }
+
+    /// Keep the history of client, of every client if None. The history is only in memory
+    pub fn keep_history(&mut self, client: Option<u16>) {
+        self.history = Some(History::new(client));
+    }
+
+    /// Steps of client since keep_history has been called
+    pub fn history(&self, client: u16) -> &[Step] {
+        self.history
+            .as_ref()
+            .map_or(&[], |history| history.of(client))
+    }
+
+    pub fn process(&mut self, operation: Operation) -> Outcome {
+        // A transfer is in the history of both clients
+        let to_client = match operation {
+            Operation::Transfer { to_client, .. } if to_client != operation.client() => {
+                Some(to_client)
+            }
+            _ => None,
+        };
+        let kept: Vec<u16> = std::iter::once(operation.client())
+            .chain(to_client)
+            .filter(|client| {
+                self.history
+                    .as_ref()
+                    .map_or(false, |history| history.is_kept(*client))
+            })
+            .collect();
+        if kept.is_empty() {
+            return self.process_(operation);
+        }
+        let currency = self.currency(&operation);
+        let outcome = self.process_(operation.clone());
+        for client in kept {
+            self.record(
+                client,
+                operation.tx(),
+                Some(operation.clone()),
+                outcome.clone(),
+                currency,
+            );
+        }
+        outcome
+    }
+
+    /// Add to the history a row of client that is not a valid operation
+    pub fn record_rejected(&mut self, client: u16, tx: u32, reason: RejectReason) {
+        self.record(client, tx, None, Err(reason), None);
+    }
+
+    fn record(
+        &mut self,
+        client: u16,
+        tx: u32,
+        operation: Option<Operation>,
+        outcome: Outcome,
+        currency: Option<Currency>,
+    ) {
+        let (balance, locked) = match self.db.get_client(client) {
+            None => (None, false),
+            Some(state) => (
+                currency.and_then(|currency| state.balances.get(&currency).cloned()),
+                state.locked,
+            ),
+        };
+        if let Some(history) = &mut self.history {
+            history.push(
+                client,
+                Step {
+                    tx,
+                    operation,
+                    outcome,
+                    balance,
+                    locked,
+                },
+            )
+        }
+    }
+
+    // Currency of the amount moved by operation, None for admin operations or if the linked tx
+    // is unknown
+    fn currency(&self, operation: &Operation) -> Option<Currency> {
+        match operation {
+            Operation::Deposit { amount, .. }
+            | Operation::Withdrawal { amount, .. }
+            | Operation::Transfer { amount, .. } => Some(amount.currency()),
+            Operation::Freeze { .. } | Operation::Unfreeze { .. } | Operation::Close { .. } => None,
+            _ => self
+                .db
+                .get_tx(operation.tx())
+                .filter(|linked| linked.client_id == operation.client())
+                .map(|linked| linked.parsed_tx.get_amount().currency()),
+        }
+    }
+
+    fn process_(&mut self, operation: Operation) -> Outcome {
+        match operation {
+            Operation::Deposit { client, tx, amount } => {
+                if amount.is_debt() {
+                    return Err(RejectReason::NegativeAmount(amount.to_string()));
+                }
+                self.apply(client, tx, Transaction::new_deposit(amount))
+            }
+            Operation::Withdrawal { client, tx, amount } => {
+                if amount.is_debt() {
+                    return Err(RejectReason::NegativeAmount(amount.to_string()));
+                }
+                let amount = amount.neg().ok_or(RejectReason::Overflow)?;
+                self.apply(client, tx, Transaction::new_withdrawl(amount))
+            }
+            // A deposit is a Credit so a Debt is needed for dispute and
+            // chargeback and a Credit is needed for resolve, a withdrawal is
+            // a Debt so the signs are inverted
+            Operation::Dispute {
+                client,
+                tx,
+                amount,
+                key,
+            } => self.keyed(key, |engine| {
+                let amount = engine.linked_amount(client, tx, amount, StoredTx::disputable)?;
+                let amount = amount.neg().ok_or(RejectReason::Overflow)?;
+                engine.apply_linked(client, tx, Transaction::new_dispute(amount))
+            }),
+            Operation::Resolve {
+                client,
+                tx,
+                amount,
+                key,
+            } => self.keyed(key, |engine| {
+                let amount = engine.linked_amount(client, tx, amount, StoredTx::held)?;
+                engine.apply_linked(client, tx, Transaction::new_resolve(amount))
+            }),
+            Operation::Chargeback {
+                client,
+                tx,
+                amount,
+                key,
+            } => self.keyed(key, |engine| {
+                let amount = engine.linked_amount(client, tx, amount, StoredTx::held)?;
+                let amount = amount.neg().ok_or(RejectReason::Overflow)?;
+                engine.apply_linked(client, tx, Transaction::new_chargeback(amount))
+            }),
+            Operation::Transfer {
+                client,
+                to_client,
+                tx,
+                amount,
+            } => self.transfer(None, client, to_client, tx, amount),
+            Operation::Freeze { client, reason, .. } => {
+                self.admin(client, |state| state.freeze(reason))
+            }
+            Operation::Unfreeze { client, reason, .. } => {
+                self.admin(client, |state| state.unfreeze(reason))
+            }
+            Operation::Close { client, reason, .. } => {
+                self.admin(client, |state| state.close(reason))
+            }
+        }
+    }
+
+    /// Persist the changes made by the rows up to line, see Store::commit
+    pub fn commit(&mut self, line: u64, input: u64) -> std::io::Result<()> {
+        self.db.commit(line, input)
+    }
+
+    /// Last line committed, when resuming the rows up to it must be skipped
+    pub fn committed(&self) -> u64 {
+        self.db.committed()
+    }
+
+    /// Identity of the rows up to the last line committed, 0 if unknown
+    pub fn committed_input(&self) -> u64 {
+        self.db.committed_input()
+    }
+
+    /// State of every client and disputable tx, see Db::from_snapshot to restore it
+    pub fn snapshot(&self) -> Snapshot {
+        self.db.snapshot()
+    }
+
+    /// Snapshot of a client, None if the client has never been seen
+    pub fn client(&self, id: u16) -> Option<Client> {
+        self.db.get_client(id).cloned()
+    }
+
+    pub fn clients(&self) -> impl Iterator<Item = (u16, &Client)> {
+        self.db.get_clients()
+    }
+
+    /// Client of the disputable tx, None if the tx is unknown or has been dropped
+    pub fn tx_client(&self, tx: u32) -> Option<u16> {
+        self.db.get_tx(tx).map(|linked| linked.client_id)
+    }
+
+    /// True if id is the tx id or the key of a row that has been applied
+    pub fn has_id(&self, id: u32) -> bool {
+        self.db.has_id(id)
+    }
+
+    // The id is reserved only if the tx is applied, a failed tx can be sent again with the same
+    // id and a duplicate is always a tx that has been applied
+    fn apply(&mut self, client_id: u16, tx_id: u32, parsed_tx: Transaction) -> Outcome {
+        if self.db.has_id(tx_id) {
+            return self.duplicate(tx_id);
+        }
+        crate::engine::engine(&mut self.db, parsed_tx.clone(), client_id, self.frozen)?;
+        self.db.add_id(tx_id);
+        // Only applied txs can be disputed
+        if self.policy.is_disputable(&parsed_tx) {
+            self.db.add_tx(tx_id, parsed_tx, client_id)
+        }
+        Ok(())
+    }
+
+    // tx_id has already been applied
+    fn duplicate(&self, tx_id: u32) -> Outcome {
+        match self.duplicates {
+            DuplicatePolicy::Reject => Err(RejectReason::DuplicateTx(tx_id)),
+            DuplicatePolicy::Ignore => Ok(()),
+        }
+    }
+
+    /// Apply a transfer from a client of this engine to a client of to, for engines that split
+    /// the clients between them. The tx id is reserved in this engine. Any other operation is
+    /// processed by this engine
+    pub fn process_across(&mut self, to: &mut Engine<S>, operation: Operation) -> Outcome {
+        let (client, to_client, tx, amount) = match operation {
+            Operation::Transfer {
+                client,
+                to_client,
+                tx,
+                amount,
+            } => (client, to_client, tx, amount),
+            operation => return self.process(operation),
+        };
+        let outcome = self.transfer(Some(&mut *to), client, to_client, tx, amount);
+        if self.history.is_some() || to.history.is_some() {
+            let currency = Some(amount.currency());
+            self.record(
+                client,
+                tx,
+                Some(operation.clone()),
+                outcome.clone(),
+                currency,
+            );
+            to.record(to_client, tx, Some(operation), outcome.clone(), currency);
+        }
+        outcome
+    }
+
+    // to_client is in to, in this engine if None
+    fn transfer(
+        &mut self,
+        to: Option<&mut Engine<S>>,
+        client: u16,
+        to_client: u16,
+        tx: u32,
+        amount: Amount,
+    ) -> Outcome {
+        if amount.is_debt() {
+            return Err(RejectReason::NegativeAmount(amount.to_string()));
+        }
+        if to_client == client {
+            return Err(RejectReason::SelfTransfer);
+        }
+        if self.db.has_id(tx) {
+            return self.duplicate(tx);
+        }
+        let to_state = match &to {
+            Some(to) => to.db.get_client(to_client),
+            None => self.db.get_client(to_client),
+        };
+        let (from_state, to_state) =
+            crate::engine::transfer(self.db.get_client(client), to_state, amount)?;
+        self.db.add_id(tx);
+        self.db.add_client(client, from_state);
+        match to {
+            Some(to) => to.db.add_client(to_client, to_state),
+            None => self.db.add_client(to_client, to_state),
+        }
+        Ok(())
+    }
+
+    // The key of a row is reserved only if the row is applied, as the id of a deposit
+    fn keyed<F: FnOnce(&mut Self) -> Outcome>(
+        &mut self,
+        key: Option<u32>,
+        operation: F,
+    ) -> Outcome {
+        if let Some(key) = key {
+            if self.db.has_id(key) {
+                return self.duplicate(key);
+            }
+            operation(self)?;
+            self.db.add_id(key);
+            return Ok(());
+        }
+        operation(self)
+    }
+
+    // amount with the sign of the linked tx, default(linked tx) if amount is None
+    fn linked_amount(
+        &mut self,
+        client_id: u16,
+        tx_id: u32,
+        amount: Option<Amount>,
+        default: fn(&StoredTx) -> Amount,
+    ) -> Result<Amount, RejectReason> {
+        let linked_tx = self
+            .db
+            .get_tx(tx_id)
+            .ok_or(RejectReason::UnknownLinkedTx(tx_id))?;
+        if linked_tx.client_id != client_id {
+            return Err(RejectReason::ClientMismatch(linked_tx.client_id));
+        }
+        let amount = match amount {
+            Some(amount) if amount.is_debt() => {
+                return Err(RejectReason::NegativeAmount(amount.to_string()))
+            }
+            Some(amount) => amount,
+            None => default(linked_tx),
+        };
+        if linked_tx.parsed_tx.get_amount().is_debt() {
+            amount.neg().ok_or(RejectReason::Overflow)
+        } else {
+            Ok(amount)
+        }
+    }
+
+    // parsed_tx is a dispute, resolve or chargeback of the stored tx tx_id. The rest of a
+    // dispute already partly settled can still be settled on an account locked by a chargeback
+    fn apply_linked(&mut self, client_id: u16, tx_id: u32, parsed_tx: Transaction) -> Outcome {
+        let linked = self
+            .db
+            .get_tx(tx_id)
+            .ok_or(RejectReason::UnknownLinkedTx(tx_id))?;
+        let next = linked.next(&parsed_tx)?;
+        let settling = !linked.closed().is_zero() && !matches!(parsed_tx, Transaction::Dispute(_));
+        let charged_back = self.db.get_client(client_id).map_or(false, |client| {
+            client.lock_reason.as_deref() == Some("chargeback")
+        });
+        let frozen = if settling && charged_back {
+            FrozenPolicy {
+                resolves: true,
+                chargebacks: true,
+                ..self.frozen
+            }
+        } else {
+            self.frozen
+        };
+        crate::engine::engine(&mut self.db, parsed_tx, client_id, frozen)?;
+        self.db
+            .set_tx_state(tx_id, next.state, next.held, next.closed);
+        Ok(())
+    }
+
+    // An admin operation on a client never seen before creates it
+    fn admin<F: FnOnce(&mut Client) -> Outcome>(
+        &mut self,
+        client_id: u16,
+        operation: F,
+    ) -> Outcome {
+        match self.db.get_client_mut(client_id) {
+            Some(client) => operation(client),
+            None => {
+                let mut client = Client::new();
+                operation(&mut client)?;
+                self.db.add_client(client_id, client);
+                Ok(())
+            }
+        }
+    }
+}
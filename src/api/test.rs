@@ -0,0 +1,568 @@
+use super::{Engine, Operation};
+use crate::db::Limits;
+use crate::engine::{Amount, Currency, DisputePolicy, DuplicatePolicy, RejectReason};
+
+fn freeze(tx: u32) -> Operation {
+    Operation::Freeze {
+        client: 1,
+        tx,
+        reason: "review".to_string(),
+    }
+}
+
+fn unfreeze(tx: u32) -> Operation {
+    Operation::Unfreeze {
+        client: 1,
+        tx,
+        reason: "cleared".to_string(),
+    }
+}
+
+fn usd(amount: &str) -> Amount {
+    Amount::parse(Currency::Usd, amount).unwrap()
+}
+
+fn engine() -> Engine {
+    Engine::new(DisputePolicy::default(), Limits::default())
+}
+
+fn deposit(tx: u32, amount: &str) -> Operation {
+    Operation::Deposit {
+        client: 1,
+        tx,
+        amount: usd(amount),
+    }
+}
+
+fn withdrawal(tx: u32, amount: &str) -> Operation {
+    Operation::Withdrawal {
+        client: 1,
+        tx,
+        amount: usd(amount),
+    }
+}
+
+#[test]
+fn process_returns_an_outcome_per_operation() {
+    let mut engine = engine();
+    assert_eq!(engine.process(deposit(1, "10")), Ok(()));
+    assert_eq!(
+        engine.process(withdrawal(2, "11")),
+        Err(RejectReason::InsufficientFunds)
+    );
+    assert_eq!(engine.process(withdrawal(3, "4")), Ok(()));
+    assert_eq!(
+        engine.process(Operation::Resolve {
+            client: 1,
+            tx: 1,
+            amount: None,
+            key: None
+        }),
+        Err(RejectReason::NotDisputed)
+    );
+    assert_eq!(
+        engine.process(Operation::Dispute {
+            client: 2,
+            tx: 1,
+            amount: None,
+            key: None
+        }),
+        Err(RejectReason::ClientMismatch(1))
+    );
+    assert_eq!(
+        engine.process(Operation::Dispute {
+            client: 1,
+            tx: 9,
+            amount: None,
+            key: None
+        }),
+        Err(RejectReason::UnknownLinkedTx(9))
+    );
+}
+
+#[test]
+fn negative_amounts_are_rejected() {
+    let mut engine = engine();
+    assert_eq!(
+        engine.process(deposit(1, "-1")),
+        Err(RejectReason::NegativeAmount("-1.0000".to_string()))
+    );
+    assert_eq!(
+        engine.process(withdrawal(2, "-1")),
+        Err(RejectReason::NegativeAmount("-1.0000".to_string()))
+    );
+    assert!(engine.client(1).is_none());
+}
+
+#[test]
+fn client_is_a_snapshot() {
+    let mut engine = engine();
+    engine.process(deposit(1, "10")).unwrap();
+    let snapshot = engine.client(1).unwrap();
+    engine
+        .process(Operation::Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+            key: None,
+        })
+        .unwrap();
+    engine
+        .process(Operation::Chargeback {
+            client: 1,
+            tx: 1,
+            amount: None,
+            key: None,
+        })
+        .unwrap();
+
+    assert!(!snapshot.locked);
+    assert_eq!(snapshot.balances[&Currency::Usd].total, usd("10"));
+    let client = engine.client(1).unwrap();
+    assert!(client.locked);
+    assert!(client.balances[&Currency::Usd].total.is_zero());
+    assert_eq!(engine.clients().count(), 1);
+}
+
+#[test]
+fn duplicate_ids_are_rejected() {
+    let mut engine = engine();
+    engine.process(deposit(1, "10")).unwrap();
+    assert_eq!(
+        engine.process(deposit(1, "10")),
+        Err(RejectReason::DuplicateTx(1))
+    );
+    // The id of a failed withdrawal is not reserved
+    assert_eq!(
+        engine.process(withdrawal(2, "20")),
+        Err(RejectReason::InsufficientFunds)
+    );
+    engine.process(withdrawal(2, "5")).unwrap();
+    assert_eq!(
+        engine.process(withdrawal(2, "5")),
+        Err(RejectReason::DuplicateTx(2))
+    );
+    assert_eq!(
+        engine.client(1).unwrap().balances[&Currency::Usd].total,
+        usd("5")
+    );
+}
+
+#[test]
+fn ignored_duplicates_retry_the_rejected_txs() {
+    let mut engine = engine();
+    engine.set_duplicates(DuplicatePolicy::Ignore);
+    engine.process(freeze(1)).unwrap();
+    assert_eq!(
+        engine.process(deposit(2, "10")),
+        Err(RejectReason::AccountLocked)
+    );
+    engine.process(unfreeze(3)).unwrap();
+    engine.process(deposit(2, "10")).unwrap();
+    engine.process(deposit(2, "10")).unwrap();
+    assert_eq!(
+        engine.client(1).unwrap().balances[&Currency::Usd].total,
+        usd("10")
+    );
+}
+
+#[test]
+fn replaying_with_ignored_duplicates_is_a_no_op() {
+    let mut engine = engine();
+    engine.set_duplicates(DuplicatePolicy::Ignore);
+    let operations = [
+        deposit(1, "10"),
+        withdrawal(2, "3"),
+        deposit(3, "5"),
+        Operation::Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+            key: None,
+        },
+        Operation::Dispute {
+            client: 1,
+            tx: 3,
+            amount: Some(usd("2")),
+            key: Some(4),
+        },
+        Operation::Resolve {
+            client: 1,
+            tx: 3,
+            amount: Some(usd("1")),
+            key: Some(5),
+        },
+    ];
+    for operation in operations.iter().chain(operations.iter()) {
+        let _ = engine.process(operation.clone());
+    }
+    let balance = &engine.client(1).unwrap().balances[&Currency::Usd];
+    assert_eq!(balance.total, usd("12"));
+    assert_eq!(balance.held, Some(usd("11")));
+}
+
+#[test]
+fn keys_of_partial_rows_are_tx_ids() {
+    let mut engine = engine();
+    engine.process(deposit(1, "10")).unwrap();
+    let dispute = Operation::Dispute {
+        client: 1,
+        tx: 1,
+        amount: Some(usd("2")),
+        key: Some(1),
+    };
+    assert_eq!(engine.process(dispute), Err(RejectReason::DuplicateTx(1)));
+    let dispute = Operation::Dispute {
+        client: 1,
+        tx: 1,
+        amount: Some(usd("2")),
+        key: Some(2),
+    };
+    engine.process(dispute.clone()).unwrap();
+    assert_eq!(engine.process(dispute), Err(RejectReason::DuplicateTx(2)));
+    assert_eq!(
+        engine.process(deposit(2, "1")),
+        Err(RejectReason::DuplicateTx(2))
+    );
+}
+
+#[test]
+fn history_keeps_the_balance_after_every_step() {
+    let mut engine = engine();
+    engine.keep_history(Some(1));
+    engine.process(deposit(1, "10")).unwrap();
+    let _ = engine.process(withdrawal(2, "20"));
+    engine
+        .process(Operation::Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+            key: None,
+        })
+        .unwrap();
+    engine.record_rejected(1, 3, RejectReason::UnknownType("bogus".to_string()));
+    engine
+        .process(Operation::Chargeback {
+            client: 1,
+            tx: 1,
+            amount: None,
+            key: None,
+        })
+        .unwrap();
+    engine
+        .process(Operation::Deposit {
+            client: 2,
+            tx: 4,
+            amount: usd("1"),
+        })
+        .unwrap();
+
+    let steps = engine.history(1);
+    let outcomes: Vec<_> = steps.iter().map(|step| step.outcome.clone()).collect();
+    assert_eq!(
+        outcomes,
+        vec![
+            Ok(()),
+            Err(RejectReason::InsufficientFunds),
+            Ok(()),
+            Err(RejectReason::UnknownType("bogus".to_string())),
+            Ok(()),
+        ]
+    );
+    let totals: Vec<_> = steps
+        .iter()
+        .map(|step| step.balance.as_ref().map(|balance| balance.total))
+        .collect();
+    assert_eq!(
+        totals,
+        vec![
+            Some(usd("10")),
+            Some(usd("10")),
+            Some(usd("10")),
+            None,
+            Some(usd("0")),
+        ]
+    );
+    assert_eq!(steps[2].balance.as_ref().unwrap().held, Some(usd("10")));
+    assert!(!steps[3].locked && steps[4].locked);
+    // Only the history of client 1 is kept
+    assert!(engine.history(2).is_empty());
+}
+
+#[test]
+fn frozen_accounts_apply_only_the_allowed_kinds() {
+    let mut engine = engine();
+    engine.set_frozen("dispute,resolve".parse().unwrap());
+    engine.process(deposit(1, "10")).unwrap();
+    engine.process(freeze(2)).unwrap();
+    assert_eq!(
+        engine.client(1).unwrap().lock_reason.as_deref(),
+        Some("review")
+    );
+    assert_eq!(
+        engine.process(deposit(3, "5")),
+        Err(RejectReason::AccountLocked)
+    );
+    engine
+        .process(Operation::Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+            key: None,
+        })
+        .unwrap();
+    engine
+        .process(Operation::Resolve {
+            client: 1,
+            tx: 1,
+            amount: None,
+            key: None,
+        })
+        .unwrap();
+    engine.process(unfreeze(4)).unwrap();
+    engine.process(deposit(5, "5")).unwrap();
+    let client = engine.client(1).unwrap();
+    assert!(!client.locked && client.lock_reason.is_none());
+    assert_eq!(client.admin_reason.as_deref(), Some("cleared"));
+    assert_eq!(client.balances[&Currency::Usd].total, usd("15"));
+}
+
+#[test]
+fn charged_back_accounts_can_be_unfrozen_but_not_closed_ones() {
+    let mut engine = engine();
+    engine.process(deposit(1, "10")).unwrap();
+    engine
+        .process(Operation::Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+            key: None,
+        })
+        .unwrap();
+    engine
+        .process(Operation::Chargeback {
+            client: 1,
+            tx: 1,
+            amount: None,
+            key: None,
+        })
+        .unwrap();
+    engine.process(unfreeze(2)).unwrap();
+    engine.process(deposit(3, "5")).unwrap();
+    engine
+        .process(Operation::Close {
+            client: 1,
+            tx: 4,
+            reason: "fraud".to_string(),
+        })
+        .unwrap();
+    assert_eq!(
+        engine.process(unfreeze(5)),
+        Err(RejectReason::AccountClosed)
+    );
+    assert_eq!(engine.process(freeze(6)), Err(RejectReason::AccountClosed));
+    let client = engine.client(1).unwrap();
+    assert!(client.locked && client.closed);
+    assert_eq!(client.balances[&Currency::Usd].total, usd("5"));
+}
+
+#[test]
+fn deposits_pay_the_debt_of_a_chargeback_first() {
+    let mut engine = engine();
+    engine.process(deposit(1, "10")).unwrap();
+    engine.process(withdrawal(2, "8")).unwrap();
+    engine
+        .process(Operation::Dispute {
+            client: 1,
+            tx: 1,
+            amount: None,
+            key: None,
+        })
+        .unwrap();
+    engine
+        .process(Operation::Chargeback {
+            client: 1,
+            tx: 1,
+            amount: None,
+            key: None,
+        })
+        .unwrap();
+    let balance = &engine.client(1).unwrap().balances[&Currency::Usd];
+    assert_eq!(balance.total, usd("-8"));
+    assert_eq!(balance.avaiable_amount(), usd("-8"));
+
+    engine.process(unfreeze(3)).unwrap();
+    engine.process(deposit(4, "5")).unwrap();
+    assert_eq!(
+        engine.process(withdrawal(5, "1")),
+        Err(RejectReason::InsufficientFunds)
+    );
+    engine.process(deposit(6, "5")).unwrap();
+    engine.process(withdrawal(7, "2")).unwrap();
+    let balance = &engine.client(1).unwrap().balances[&Currency::Usd];
+    assert!(balance.total.is_zero());
+}
+
+#[test]
+fn partial_disputes_hold_and_charge_back_part_of_a_deposit() {
+    let mut engine = engine();
+    engine.process(deposit(1, "10")).unwrap();
+    let dispute = |amount: Option<&str>| Operation::Dispute {
+        client: 1,
+        tx: 1,
+        amount: amount.map(usd),
+        key: None,
+    };
+    engine.process(dispute(Some("3"))).unwrap();
+    engine.process(dispute(Some("4"))).unwrap();
+    assert_eq!(
+        engine.process(dispute(Some("4"))),
+        Err(RejectReason::ExceedsLinkedAmount("4.0000".to_string()))
+    );
+    let balance = &engine.client(1).unwrap().balances[&Currency::Usd];
+    assert_eq!(balance.held, Some(usd("7")));
+
+    engine
+        .process(Operation::Resolve {
+            client: 1,
+            tx: 1,
+            amount: Some(usd("5")),
+            key: None,
+        })
+        .unwrap();
+    // Only what has never been disputed is left
+    engine.process(dispute(None)).unwrap();
+    let balance = &engine.client(1).unwrap().balances[&Currency::Usd];
+    assert_eq!(balance.held, Some(usd("5")));
+    engine
+        .process(Operation::Chargeback {
+            client: 1,
+            tx: 1,
+            amount: None,
+            key: None,
+        })
+        .unwrap();
+    let balance = &engine.client(1).unwrap().balances[&Currency::Usd];
+    assert_eq!(balance.total, usd("5"));
+    assert_eq!(balance.held, None);
+    assert_eq!(
+        engine.process(dispute(None)),
+        Err(RejectReason::DisputeClosed)
+    );
+}
+
+#[test]
+fn partial_chargebacks_let_the_rest_of_the_dispute_be_settled() {
+    let mut engine = engine();
+    engine.process(deposit(1, "10")).unwrap();
+    engine.process(deposit(2, "10")).unwrap();
+    let settle = |tx, chargeback: bool, amount: &str| {
+        let amount = Some(usd(amount));
+        if chargeback {
+            Operation::Chargeback {
+                client: 1,
+                tx,
+                amount,
+                key: None,
+            }
+        } else {
+            Operation::Resolve {
+                client: 1,
+                tx,
+                amount,
+                key: None,
+            }
+        }
+    };
+    for tx in [1, 2].iter() {
+        engine
+            .process(Operation::Dispute {
+                client: 1,
+                tx: *tx,
+                amount: Some(usd("6")),
+                key: None,
+            })
+            .unwrap();
+    }
+    engine.process(settle(1, true, "2")).unwrap();
+    assert!(engine.client(1).unwrap().locked);
+    engine.process(settle(1, false, "3")).unwrap();
+    engine.process(settle(1, true, "1")).unwrap();
+    // Other txs still follow the frozen policy
+    assert_eq!(
+        engine.process(settle(2, false, "6")),
+        Err(RejectReason::AccountLocked)
+    );
+    let balance = &engine.client(1).unwrap().balances[&Currency::Usd];
+    assert_eq!(balance.total, usd("17"));
+    assert_eq!(balance.held, Some(usd("6")));
+}
+
+#[test]
+fn transfers_apply_to_both_clients_or_none() {
+    let mut engine = engine();
+    engine.process(deposit(1, "10")).unwrap();
+    let transfer = |tx, amount| Operation::Transfer {
+        client: 1,
+        to_client: 2,
+        tx,
+        amount: usd(amount),
+    };
+    engine.process(transfer(2, "4")).unwrap();
+    assert_eq!(
+        engine.process(transfer(3, "7")),
+        Err(RejectReason::InsufficientFunds)
+    );
+    assert_eq!(
+        engine.process(transfer(2, "1")),
+        Err(RejectReason::DuplicateTx(2))
+    );
+    engine
+        .process(Operation::Freeze {
+            client: 2,
+            tx: 4,
+            reason: "review".to_string(),
+        })
+        .unwrap();
+    assert_eq!(
+        engine.process(transfer(5, "1")),
+        Err(RejectReason::AccountLocked)
+    );
+
+    let total = |client| engine.client(client).unwrap().balances[&Currency::Usd].total;
+    assert_eq!(total(1), usd("6"));
+    assert_eq!(total(2), usd("4"));
+}
+
+#[test]
+fn transfers_ignore_the_frozen_policy() {
+    let mut engine = engine();
+    engine.set_frozen("deposit,withdraw".parse().unwrap());
+    engine.process(deposit(1, "10")).unwrap();
+    engine.process(freeze(2)).unwrap();
+    let transfer = |client, to_client, tx| Operation::Transfer {
+        client,
+        to_client,
+        tx,
+        amount: usd("1"),
+    };
+    assert_eq!(
+        engine.process(transfer(1, 2, 3)),
+        Err(RejectReason::AccountLocked)
+    );
+    assert_eq!(
+        engine.process(transfer(2, 1, 4)),
+        Err(RejectReason::AccountLocked)
+    );
+    engine.process(unfreeze(5)).unwrap();
+    assert_eq!(
+        engine.process(transfer(1, 1, 6)),
+        Err(RejectReason::SelfTransfer)
+    );
+    // A rejected self transfer does not reserve its tx id
+    engine.process(deposit(6, "1")).unwrap();
+    assert_eq!(
+        engine.client(1).unwrap().balances[&Currency::Usd].total,
+        usd("11")
+    );
+}
@@ -0,0 +1,337 @@
+use super::{Db, Limits, Snapshot, Store, Transaction, TxState};
+use crate::engine::{Amount, Client};
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeSet;
+use std::fs::{File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+const WAL: &str = "wal.jsonl";
+const CHECKPOINT: &str = "checkpoint.json";
+
+// A line of the wal, the entries between two commits are applied only if the second commit
+// has been written. Commits are numbered by seq as many of them can have the same input line
+#[derive(Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+enum Entry {
+    Id {
+        id: u32,
+    },
+    Tx {
+        id: u32,
+        tx: crate::engine::Transaction,
+        client: u16,
+    },
+    TxState {
+        id: u32,
+        state: TxState,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        held: Option<Amount>,
+        #[serde(default, skip_serializing_if = "Option::is_none")]
+        closed: Option<Amount>,
+    },
+    Client {
+        id: u16,
+        client: Client,
+    },
+    Commit {
+        seq: u64,
+        line: u64,
+        #[serde(default)]
+        input: u64,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+struct Checkpoint {
+    seq: u64,
+    line: u64,
+    #[serde(default)]
+    input: u64,
+    state: Snapshot,
+}
+
+/// Db persisted in a directory: a checkpoint of the whole state plus a write-ahead log of the
+/// changes made after it. Committed changes survive a crash of the process, the checkpoint is
+/// synced to disk so after a crash of the machine the changes committed after it can be lost
+pub struct DurableDb {
+    db: Db,
+    dir: PathBuf,
+    wal: BufWriter<File>,
+    // Clients that can have been changed since the last commit
+    dirty: BTreeSet<u16>,
+    // Number of the last commit
+    seq: u64,
+    // Last committed input line and identity of the input up to it
+    line: u64,
+    input: u64,
+    // Min size of the wal before a checkpoint
+    min_wal: u64,
+    wal_size: u64,
+    checkpoint_size: u64,
+    // First failed write to the wal or checkpoint, once set every commit fails as the wal
+    // misses some changes
+    error: Option<std::io::Error>,
+}
+
+impl DurableDb {
+    /// Open the store in dir recovering the committed state, dir is created if missing. The
+    /// wal is checkpointed when it is bigger than both min_wal bytes and the last checkpoint,
+    /// so the time spent writing checkpoints grows linearly with the committed rows
+    pub fn open<P: AsRef<Path>>(dir: P, limits: Limits, min_wal: u64) -> std::io::Result<Self> {
+        let dir = dir.as_ref().to_path_buf();
+        std::fs::create_dir_all(&dir)?;
+
+        let (mut db, mut committed) = match File::open(dir.join(CHECKPOINT)) {
+            Ok(file) => {
+                let checkpoint: Checkpoint = serde_json::from_reader(BufReader::new(file))?;
+                let db = Db::from_snapshot(checkpoint.state, limits).map_err(invalid_data)?;
+                (db, (checkpoint.seq, checkpoint.line, checkpoint.input))
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (Db::new(limits), (0, 0, 0)),
+            Err(e) => return Err(e),
+        };
+        if let Ok(file) = File::open(dir.join(WAL)) {
+            committed = replay(&mut db, committed, file)?;
+        }
+        let (seq, line, input) = committed;
+
+        // The wal is truncated only after the recovered state is in the checkpoint
+        let wal = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(dir.join(WAL))?;
+        let wal = BufWriter::new(wal);
+        let mut store = DurableDb {
+            db,
+            dir,
+            wal,
+            dirty: BTreeSet::new(),
+            seq,
+            line,
+            input,
+            min_wal,
+            wal_size: 0,
+            checkpoint_size: 0,
+            error: None,
+        };
+        store.checkpoint()?;
+        Ok(store)
+    }
+
+    /// Write the whole state and truncate the wal
+    pub fn checkpoint(&mut self) -> std::io::Result<()> {
+        let checkpoint = Checkpoint {
+            seq: self.seq,
+            line: self.line,
+            input: self.input,
+            state: self.db.snapshot(),
+        };
+        let tmp = self.dir.join(format!("{}.tmp", CHECKPOINT));
+        let mut file = BufWriter::new(File::create(&tmp)?);
+        serde_json::to_writer(&mut file, &checkpoint)?;
+        file.flush()?;
+        file.get_ref().sync_all()?;
+        self.checkpoint_size = file.get_ref().metadata()?.len();
+        std::fs::rename(&tmp, self.dir.join(CHECKPOINT))?;
+        sync_dir(&self.dir)?;
+
+        self.wal = BufWriter::new(File::create(self.dir.join(WAL))?);
+        self.wal_size = 0;
+        Ok(())
+    }
+
+    // Store::commit, the error is kept by the caller
+    fn commit_(&mut self, line: u64, input: u64) -> std::io::Result<()> {
+        for id in std::mem::take(&mut self.dirty) {
+            if let Some(client) = self.db.get_client(id) {
+                let entry = Entry::Client {
+                    id,
+                    client: client.clone(),
+                };
+                self.log(&entry);
+            }
+        }
+        if line >= self.line {
+            self.line = line;
+            self.input = input;
+        }
+        self.seq += 1;
+        self.log(&Entry::Commit {
+            seq: self.seq,
+            line: self.line,
+            input: self.input,
+        });
+        if let Some(e) = &self.error {
+            return Err(std::io::Error::new(e.kind(), e.to_string()));
+        }
+        self.wal.flush()?;
+        if self.wal_size >= self.min_wal.max(self.checkpoint_size) {
+            self.checkpoint()?;
+        }
+        Ok(())
+    }
+
+    fn log(&mut self, entry: &Entry) {
+        if self.error.is_none() {
+            match write_entry(&mut self.wal, entry) {
+                Ok(size) => self.wal_size += size,
+                Err(e) => self.error = Some(e),
+            }
+        }
+    }
+}
+
+// The rename of the checkpoint must reach the disk before the wal is truncated
+#[cfg(unix)]
+fn sync_dir(dir: &Path) -> std::io::Result<()> {
+    File::open(dir)?.sync_all()
+}
+
+#[cfg(not(unix))]
+fn sync_dir(_dir: &Path) -> std::io::Result<()> {
+    Ok(())
+}
+
+// Apply the entries of the commits after seq, a torn last line is ignored even if it ends in the
+// middle of a multi byte char. Return the last commit seq, line and input
+fn replay(
+    db: &mut Db,
+    (mut seq, mut line, mut input): (u64, u64, u64),
+    wal: File,
+) -> std::io::Result<(u64, u64, u64)> {
+    let mut pending = vec![];
+    for text in BufReader::new(wal).split(b'\n') {
+        let entry: Entry = match serde_json::from_slice(&text?) {
+            Ok(entry) => entry,
+            Err(_) => break,
+        };
+        match entry {
+            Entry::Commit {
+                seq: committed,
+                line: committed_line,
+                input: committed_input,
+            } => {
+                // Entries older than the checkpoint are already in db
+                if committed > seq {
+                    for entry in pending.drain(..) {
+                        apply(db, entry);
+                    }
+                    seq = committed;
+                    line = committed_line;
+                    input = committed_input;
+                }
+                pending.clear();
+            }
+            entry => pending.push(entry),
+        }
+    }
+    Ok((seq, line, input))
+}
+
+fn apply(db: &mut Db, entry: Entry) {
+    match entry {
+        Entry::Id { id } => db.add_id(id),
+        Entry::Tx { id, tx, client } => db.add_tx(id, tx, client),
+        Entry::TxState {
+            id,
+            state,
+            held,
+            closed,
+        } => db.set_tx_state(id, state, held, closed),
+        Entry::Client { id, client } => db.add_client(id, client),
+        Entry::Commit { .. } => (),
+    }
+}
+
+// Return the bytes written
+fn write_entry<W: Write>(mut writer: W, entry: &Entry) -> std::io::Result<u64> {
+    let mut line = serde_json::to_vec(entry)?;
+    line.push(b'\n');
+    writer.write_all(&line)?;
+    Ok(line.len() as u64)
+}
+
+fn invalid_data(e: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, e)
+}
+
+impl Store for DurableDb {
+    fn has_id(&self, id: u32) -> bool {
+        self.db.has_id(id)
+    }
+
+    fn add_id(&mut self, id: u32) {
+        self.log(&Entry::Id { id });
+        self.db.add_id(id)
+    }
+
+    fn add_tx(&mut self, id: u32, tx: crate::engine::Transaction, client_id: u16) {
+        self.log(&Entry::Tx {
+            id,
+            tx: tx.clone(),
+            client: client_id,
+        });
+        self.db.add_tx(id, tx, client_id)
+    }
+
+    fn set_tx_state(
+        &mut self,
+        id: u32,
+        state: TxState,
+        held: Option<Amount>,
+        closed: Option<Amount>,
+    ) {
+        self.log(&Entry::TxState {
+            id,
+            state,
+            held,
+            closed,
+        });
+        self.db.set_tx_state(id, state, held, closed)
+    }
+
+    fn get_tx(&self, id: u32) -> Option<&Transaction> {
+        self.db.get_tx(id)
+    }
+
+    fn add_client(&mut self, id: u16, client: Client) {
+        self.dirty.insert(id);
+        self.db.add_client(id, client)
+    }
+
+    fn get_client(&self, id: u16) -> Option<&Client> {
+        self.db.get_client(id)
+    }
+
+    fn get_client_mut(&mut self, id: u16) -> Option<&mut Client> {
+        self.dirty.insert(id);
+        self.db.get_client_mut(id)
+    }
+
+    fn get_clients(&self) -> Box<dyn Iterator<Item = (u16, &Client)> + '_> {
+        self.db.get_clients()
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        self.db.snapshot()
+    }
+
+    fn commit(&mut self, line: u64, input: u64) -> std::io::Result<()> {
+        let result = self.commit_(line, input);
+        if let Err(e) = &result {
+            if self.error.is_none() {
+                self.error = Some(std::io::Error::new(e.kind(), e.to_string()));
+            }
+        }
+        result
+    }
+
+    fn committed(&self) -> u64 {
+        self.line
+    }
+
+    fn committed_input(&self) -> u64 {
+        self.input
+    }
+}
@@ -0,0 +1,299 @@
+use super::Transaction;
+use std::collections::{HashMap, VecDeque};
+
+// Ids per page of TxIndex
+const PAGE_SIZE: usize = 1 << 12;
+// Max txs of a sparse page of TxIndex
+const MAX_SPARSE: usize = PAGE_SIZE / 8;
+// Ids per page of IdSet, a page is 8 KiB
+const SET_PAGE_SIZE: usize = 1 << 16;
+
+/// Set of u32 stored as a bitset allocated in pages, the whole u32 range takes 512 MiB
+#[derive(Default)]
+pub struct IdSet {
+    pages: HashMap<u32, Box<[u64]>>,
+    len: usize,
+}
+
+impl IdSet {
+    pub fn contains(&self, id: u32) -> bool {
+        let (page, word, bit) = Self::position(id);
+        match self.pages.get(&page) {
+            None => false,
+            Some(words) => words[word] & bit != 0,
+        }
+    }
+
+    pub fn insert(&mut self, id: u32) {
+        let (page, word, bit) = Self::position(id);
+        let words = self
+            .pages
+            .entry(page)
+            .or_insert_with(|| vec![0; SET_PAGE_SIZE / 64].into_boxed_slice());
+        if words[word] & bit == 0 {
+            words[word] |= bit;
+            self.len += 1;
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Bytes used by the pages
+    pub fn memory(&self) -> usize {
+        self.pages.len() * SET_PAGE_SIZE / 8
+    }
+
+    /// Ids in ascending order
+    pub fn iter(&self) -> impl Iterator<Item = u32> + '_ {
+        let mut pages: Vec<&u32> = self.pages.keys().collect();
+        pages.sort_unstable();
+        pages.into_iter().flat_map(move |page| {
+            let words = &self.pages[page];
+            (0..SET_PAGE_SIZE as u32)
+                .filter(move |i| words[*i as usize / 64] & (1 << (i % 64)) != 0)
+                .map(move |i| page * SET_PAGE_SIZE as u32 + i)
+        })
+    }
+
+    /// Inclusive ranges of consecutive ids in ascending order
+    pub fn ranges(&self) -> impl Iterator<Item = (u32, u32)> + '_ {
+        let mut ids = self.iter().peekable();
+        std::iter::from_fn(move || {
+            let start = ids.next()?;
+            let mut end = start;
+            while end.checked_add(1).is_some() && ids.peek() == Some(&(end + 1)) {
+                end += 1;
+                ids.next();
+            }
+            Some((start, end))
+        })
+    }
+
+    fn position(id: u32) -> (u32, usize, u64) {
+        let offset = id as usize % SET_PAGE_SIZE;
+        (id / SET_PAGE_SIZE as u32, offset / 64, 1 << (offset % 64))
+    }
+}
+
+// Txs of a page, sparse while few ids of the page are used
+enum Slots {
+    // Sorted by slot
+    Sparse(Vec<(u16, Transaction)>),
+    Dense(Box<[Option<Transaction>]>),
+}
+
+struct Page {
+    slots: Slots,
+    len: usize,
+}
+
+impl Page {
+    fn new() -> Self {
+        Page {
+            slots: Slots::Sparse(Vec::new()),
+            len: 0,
+        }
+    }
+
+    fn get(&self, slot: usize) -> Option<&Transaction> {
+        match &self.slots {
+            Slots::Sparse(txs) => txs
+                .binary_search_by_key(&(slot as u16), |(slot, _)| *slot)
+                .ok()
+                .map(|i| &txs[i].1),
+            Slots::Dense(slots) => slots[slot].as_ref(),
+        }
+    }
+
+    fn get_mut(&mut self, slot: usize) -> Option<&mut Transaction> {
+        match &mut self.slots {
+            Slots::Sparse(txs) => match txs.binary_search_by_key(&(slot as u16), |(slot, _)| *slot)
+            {
+                Ok(i) => Some(&mut txs[i].1),
+                Err(_) => None,
+            },
+            Slots::Dense(slots) => slots[slot].as_mut(),
+        }
+    }
+
+    // Return true if the slot was empty
+    fn insert(&mut self, slot: usize, tx: Transaction) -> bool {
+        let added = match &mut self.slots {
+            Slots::Sparse(txs) => {
+                match txs.binary_search_by_key(&(slot as u16), |(slot, _)| *slot) {
+                    Ok(i) => {
+                        txs[i].1 = tx;
+                        false
+                    }
+                    Err(i) => {
+                        txs.insert(i, (slot as u16, tx));
+                        true
+                    }
+                }
+            }
+            Slots::Dense(slots) => slots[slot].replace(tx).is_none(),
+        };
+        if added {
+            self.len += 1;
+            if self.len > MAX_SPARSE {
+                self.make_dense();
+            }
+        }
+        added
+    }
+
+    fn remove(&mut self, slot: usize) -> Option<Transaction> {
+        let tx = match &mut self.slots {
+            Slots::Sparse(txs) => {
+                let i = txs
+                    .binary_search_by_key(&(slot as u16), |(slot, _)| *slot)
+                    .ok()?;
+                txs.remove(i).1
+            }
+            Slots::Dense(slots) => slots[slot].take()?,
+        };
+        self.len -= 1;
+        Some(tx)
+    }
+
+    fn make_dense(&mut self) {
+        if let Slots::Sparse(txs) = &mut self.slots {
+            let mut slots: Box<[Option<Transaction>]> = (0..PAGE_SIZE).map(|_| None).collect();
+            for (slot, tx) in txs.drain(..) {
+                slots[slot as usize] = Some(tx);
+            }
+            self.slots = Slots::Dense(slots);
+        }
+    }
+
+    fn iter(&self) -> Box<dyn Iterator<Item = (usize, &Transaction)> + '_> {
+        match &self.slots {
+            Slots::Sparse(txs) => Box::new(txs.iter().map(|(slot, tx)| (*slot as usize, tx))),
+            Slots::Dense(slots) => Box::new(
+                slots
+                    .iter()
+                    .enumerate()
+                    .filter_map(|(slot, tx)| tx.as_ref().map(|tx| (slot, tx))),
+            ),
+        }
+    }
+
+    // Bytes used by the txs of the page
+    fn memory(&self) -> usize {
+        match &self.slots {
+            Slots::Sparse(txs) => txs.capacity() * std::mem::size_of::<(u16, Transaction)>(),
+            Slots::Dense(_) => PAGE_SIZE * std::mem::size_of::<Option<Transaction>>(),
+        }
+    }
+}
+
+/// Txs stored in pages of consecutive ids, a page is a sorted vec until more than an eighth of
+/// its ids are used and then an array. When the pages use more than max_memory the oldest pages
+/// are dropped, with growing ids they hold the oldest txs
+pub struct TxIndex {
+    pages: HashMap<u32, Page>,
+    // Pages oldest first
+    page_order: VecDeque<u32>,
+    len: usize,
+    memory: usize,
+    max_memory: Option<usize>,
+}
+
+impl TxIndex {
+    /// max_memory is the max number of bytes used by the pages, at least a page is kept
+    pub fn new(max_memory: Option<usize>) -> Self {
+        TxIndex {
+            pages: HashMap::new(),
+            page_order: VecDeque::new(),
+            len: 0,
+            memory: 0,
+            max_memory,
+        }
+    }
+
+    pub fn get(&self, id: u32) -> Option<&Transaction> {
+        let (page, slot) = Self::position(id);
+        self.pages.get(&page)?.get(slot)
+    }
+
+    pub fn get_mut(&mut self, id: u32) -> Option<&mut Transaction> {
+        let (page, slot) = Self::position(id);
+        self.pages.get_mut(&page)?.get_mut(slot)
+    }
+
+    pub fn insert(&mut self, id: u32, tx: Transaction) {
+        let (page_id, slot) = Self::position(id);
+        if !self.pages.contains_key(&page_id) {
+            self.page_order.push_back(page_id);
+        }
+        let page = self.pages.entry(page_id).or_insert_with(Page::new);
+        let before = page.memory();
+        if page.insert(slot, tx) {
+            self.len += 1;
+        }
+        self.memory = self.memory + page.memory() - before;
+        self.evict(page_id);
+    }
+
+    pub fn remove(&mut self, id: u32) -> Option<Transaction> {
+        let (page_id, slot) = Self::position(id);
+        let page = self.pages.get_mut(&page_id)?;
+        let tx = page.remove(slot)?;
+        self.len -= 1;
+        if page.len == 0 {
+            self.memory -= page.memory();
+            self.pages.remove(&page_id);
+            self.page_order.retain(|page| *page != page_id);
+        }
+        Some(tx)
+    }
+
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    /// Bytes used by the pages
+    pub fn memory(&self) -> usize {
+        self.memory
+    }
+
+    /// Txs oldest page first and by id inside a page
+    pub fn iter(&self) -> impl Iterator<Item = (u32, &Transaction)> + '_ {
+        self.page_order.iter().flat_map(move |page| {
+            self.pages[page]
+                .iter()
+                .map(move |(slot, tx)| (page * PAGE_SIZE as u32 + slot as u32, tx))
+        })
+    }
+
+    // Drop the oldest pages other than keep while the pages use more than max_memory
+    fn evict(&mut self, keep: u32) {
+        if let Some(max_memory) = self.max_memory {
+            while self.memory > max_memory {
+                let oldest = match self.page_order.iter().position(|page| *page != keep) {
+                    Some(oldest) => oldest,
+                    None => break,
+                };
+                if let Some(page) = self
+                    .page_order
+                    .remove(oldest)
+                    .and_then(|oldest| self.pages.remove(&oldest))
+                {
+                    self.len -= page.len;
+                    self.memory -= page.memory();
+                }
+            }
+        }
+    }
+
+    /// Bytes used by a page with more than an eighth of its ids
+    pub fn page_memory() -> usize {
+        PAGE_SIZE * std::mem::size_of::<Option<Transaction>>()
+    }
+
+    fn position(id: u32) -> (u32, usize) {
+        (id / PAGE_SIZE as u32, id as usize % PAGE_SIZE)
+    }
+}
@@ -1,54 +1,251 @@
 // fake db just for POC
 
+mod durable;
+mod index;
+mod snapshot;
+mod store;
+#[cfg(test)]
+mod test;
+
+pub use durable::DurableDb;
+pub use index::{IdSet, TxIndex};
+pub use snapshot::Snapshot;
+pub use store::Store;
+
+use crate::engine::{Amount, RejectReason};
+use serde::{Deserialize, Serialize};
+
+/// In memory Store
 pub struct Db {
-    referenced_id: std::collections::HashSet<u32>,
-    referenced_tx: std::collections::HashMap<u32, Transaction>,
+    // Ids of every deposit and withdrawal seen, disputable or not
+    referenced_id: IdSet,
+    referenced_tx: TxIndex,
+    // Insertion order of referenced_tx used to evict the oldest tx, empty if retention is None
+    tx_order: std::collections::VecDeque<u32>,
+    // Max number of txs kept in referenced_tx, None means unbounded
+    retention: Option<usize>,
     clients: std::collections::HashMap<u16, crate::engine::Client>,
 }
 
+/// Bounds of the disputable txs kept in memory, when exceeded the oldest txs are dropped and
+/// can no longer be disputed. The ids used to detect duplicates are never dropped
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Limits {
+    /// Max number of disputable txs
+    pub retention: Option<usize>,
+    /// Max bytes used by the disputable txs, the up to 512 MiB of ids are not counted
+    pub max_memory: Option<usize>,
+}
+
+/// A disputable tx, the amounts are positive and held + closed is never more than the tx
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Transaction {
     pub parsed_tx: crate::engine::Transaction,
     pub client_id: u16,
+    pub state: TxState,
+    /// Amount under dispute, None if it follows from state (all of the tx if Disputed)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub held: Option<Amount>,
+    /// Amount resolved or charged back, None if it follows from state (all of the tx if
+    /// Resolved or ChargedBack)
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub closed: Option<Amount>,
+}
+
+/// Last transition of a stored tx: Processed -> Disputed -> Resolved | ChargedBack, a tx
+/// partially disputed can be Disputed again while part of it has not been disputed
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum TxState {
+    Processed,
+    Disputed,
+    Resolved,
+    ChargedBack,
+}
+
+impl Transaction {
+    pub fn new(parsed_tx: crate::engine::Transaction, client_id: u16) -> Self {
+        Transaction {
+            parsed_tx,
+            client_id,
+            state: TxState::Processed,
+            held: None,
+            closed: None,
+        }
+    }
+
+    /// Absolute value of the tx amount
+    pub fn amount(&self) -> Amount {
+        let amount = self.parsed_tx.get_amount();
+        if amount.is_debt() {
+            amount.neg().expect("impossible state")
+        } else {
+            amount
+        }
+    }
+
+    pub fn held(&self) -> Amount {
+        match (self.held, self.state) {
+            (Some(held), _) => held,
+            (None, TxState::Disputed) => self.amount(),
+            (None, _) => Amount::zero(self.amount().currency()),
+        }
+    }
+
+    pub fn closed(&self) -> Amount {
+        match (self.closed, self.state) {
+            (Some(closed), _) => closed,
+            (None, TxState::Resolved) | (None, TxState::ChargedBack) => self.amount(),
+            (None, _) => Amount::zero(self.amount().currency()),
+        }
+    }
+
+    /// Amount that has never been disputed
+    pub fn disputable(&self) -> Amount {
+        let left = self.amount() - self.held();
+        left.and_then(|left| left - self.closed())
+            .expect("impossible state")
+    }
+
+    /// Return this tx after applying `tx` to it, an error if the transition is illegal (eg
+    /// resolving a tx that has never been disputed) or the amount of `tx` exceeds the disputable
+    /// amount for a dispute or the held amount for a resolve or a chargeback
+    pub fn next(&self, tx: &crate::engine::Transaction) -> Result<Self, RejectReason> {
+        use crate::engine::Transaction as Tx;
+        let mut amount = tx.get_amount();
+        if amount.is_debt() {
+            amount = amount.neg().ok_or(RejectReason::Overflow)?;
+        }
+        let (held, closed) = (self.held(), self.closed());
+        let (state, held, closed) = match tx {
+            Tx::Dispute(_) => {
+                let disputable = self.disputable();
+                if disputable.is_zero() && held.is_zero() {
+                    return Err(RejectReason::DisputeClosed);
+                } else if disputable.is_zero() {
+                    return Err(RejectReason::AlreadyDisputed);
+                }
+                check_amount(amount, disputable)?;
+                let held = (held + amount).ok_or(RejectReason::Overflow)?;
+                (TxState::Disputed, held, closed)
+            }
+            Tx::Resolve(_) | Tx::Chargeback(_) => {
+                if held.is_zero() && closed.is_zero() {
+                    return Err(RejectReason::NotDisputed);
+                } else if held.is_zero() {
+                    return Err(RejectReason::DisputeClosed);
+                }
+                check_amount(amount, held)?;
+                let held = (held - amount).ok_or(RejectReason::Overflow)?;
+                let closed = (closed + amount).ok_or(RejectReason::Overflow)?;
+                let state = match tx {
+                    _ if !held.is_zero() => TxState::Disputed,
+                    Tx::Resolve(_) => TxState::Resolved,
+                    _ => TxState::ChargedBack,
+                };
+                (state, held, closed)
+            }
+            _ => return Err(RejectReason::NotDisputed),
+        };
+        Ok(Transaction {
+            state,
+            held: Some(held),
+            closed: Some(closed),
+            ..self.clone()
+        })
+    }
+}
+
+// amount must be > 0 and <= max, both positive
+fn check_amount(amount: Amount, max: Amount) -> Result<(), RejectReason> {
+    if amount.currency() != max.currency() {
+        return Err(RejectReason::CurrencyMismatch(
+            amount.currency().code().to_string(),
+        ));
+    }
+    if amount.is_zero() {
+        return Err(RejectReason::InvalidAmount(amount.to_string()));
+    }
+    match max - amount {
+        Some(left) if !left.is_debt() => Ok(()),
+        _ => Err(RejectReason::ExceedsLinkedAmount(amount.to_string())),
+    }
 }
 
 impl Db {
-    pub fn new() -> Self {
+    pub fn new(limits: Limits) -> Self {
         Db {
-            referenced_id: std::collections::HashSet::new(),
-            referenced_tx: std::collections::HashMap::new(),
+            referenced_id: IdSet::default(),
+            referenced_tx: TxIndex::new(limits.max_memory),
+            tx_order: std::collections::VecDeque::new(),
+            retention: limits.retention,
             clients: std::collections::HashMap::new(),
         }
     }
 
-    pub fn has_id(&self, id: u32) -> bool {
-        self.referenced_id.contains(&id)
+    /// Bytes used by the tx ids and the disputable txs
+    pub fn tx_memory(&self) -> usize {
+        self.referenced_id.memory() + self.referenced_tx.memory()
+    }
+}
+
+impl Store for Db {
+    fn has_id(&self, id: u32) -> bool {
+        self.referenced_id.contains(id)
     }
 
-    pub fn add_id(&mut self, id: u32) {
+    fn add_id(&mut self, id: u32) {
         self.referenced_id.insert(id);
     }
 
-    pub fn add_tx(&mut self, id: u32, tx: crate::engine::Transaction, client_id: u16) {
-        let tx = Transaction {
-            parsed_tx: tx,
-            client_id,
-        };
-        self.referenced_tx.insert(id, tx);
+    fn add_tx(&mut self, id: u32, tx: crate::engine::Transaction, client_id: u16) {
+        self.referenced_tx
+            .insert(id, Transaction::new(tx, client_id));
+        if let Some(retention) = self.retention {
+            self.tx_order.push_back(id);
+            while self.tx_order.len() > retention {
+                if let Some(oldest) = self.tx_order.pop_front() {
+                    self.referenced_tx.remove(oldest);
+                }
+            }
+        }
     }
 
-    pub fn add_client(&mut self, id: u16, client: crate::engine::Client) {
+    fn set_tx_state(
+        &mut self,
+        id: u32,
+        state: TxState,
+        held: Option<Amount>,
+        closed: Option<Amount>,
+    ) {
+        if let Some(tx) = self.referenced_tx.get_mut(id) {
+            tx.state = state;
+            tx.held = held;
+            tx.closed = closed;
+        }
+    }
+
+    fn add_client(&mut self, id: u16, client: crate::engine::Client) {
         self.clients.insert(id, client);
     }
 
-    pub fn get_tx(&mut self, id: u32) -> Option<&Transaction> {
-        self.referenced_tx.get(&id)
+    fn get_tx(&self, id: u32) -> Option<&Transaction> {
+        self.referenced_tx.get(id)
+    }
+
+    fn get_client(&self, id: u16) -> Option<&crate::engine::Client> {
+        self.clients.get(&id)
     }
 
-    pub fn get_client(&mut self, id: u16) -> Option<&mut crate::engine::Client> {
+    fn get_client_mut(&mut self, id: u16) -> Option<&mut crate::engine::Client> {
         self.clients.get_mut(&id)
     }
 
-    pub fn get_clients(&self) -> &std::collections::HashMap<u16, crate::engine::Client> {
-        &self.clients
+    fn get_clients(&self) -> Box<dyn Iterator<Item = (u16, &crate::engine::Client)> + '_> {
+        Box::new(self.clients.iter().map(|(id, client)| (*id, client)))
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        Db::snapshot(self)
     }
 }
@@ -0,0 +1,85 @@
+use super::{Db, Limits, Store, Transaction};
+use crate::engine::Client;
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
+use std::io::Write;
+
+/// Bumped every time the serialized form changes
+pub const VERSION: u32 = 3;
+
+/// Full state of a Db: the clients, the disputable txs oldest first and the ids of every
+/// deposit and withdrawal
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Snapshot {
+    pub version: u32,
+    pub clients: BTreeMap<u16, Client>,
+    pub txs: Vec<TxEntry>,
+    /// Inclusive ranges of consecutive ids, added in version 2 for version 1 only the ids of
+    /// txs are known. Version 3 added the held and closed amounts of the txs
+    #[serde(default)]
+    pub id_ranges: Vec<(u32, u32)>,
+}
+
+impl Snapshot {
+    /// Read a snapshot written by save, the version is checked by Db::from_snapshot
+    pub fn load<R: std::io::Read>(reader: R) -> std::io::Result<Self> {
+        Ok(serde_json::from_reader(std::io::BufReader::new(reader))?)
+    }
+
+    pub fn save<W: std::io::Write>(&self, writer: W) -> std::io::Result<()> {
+        let mut writer = std::io::BufWriter::new(writer);
+        serde_json::to_writer(&mut writer, self)?;
+        writer.flush()
+    }
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct TxEntry {
+    pub id: u32,
+    #[serde(flatten)]
+    pub tx: Transaction,
+}
+
+impl Db {
+    pub fn snapshot(&self) -> Snapshot {
+        Snapshot {
+            version: VERSION,
+            clients: self
+                .clients
+                .iter()
+                .map(|(id, client)| (*id, client.clone()))
+                .collect(),
+            txs: self
+                .referenced_tx
+                .iter()
+                .map(|(id, tx)| TxEntry { id, tx: tx.clone() })
+                .collect(),
+            id_ranges: self.referenced_id.ranges().collect(),
+        }
+    }
+
+    /// Rebuild a Db, if the txs exceed limits the oldest are dropped
+    pub fn from_snapshot(snapshot: Snapshot, limits: Limits) -> Result<Self, String> {
+        if snapshot.version == 0 || snapshot.version > VERSION {
+            return Err(format!(
+                "unsupported snapshot version: {} expected at most: {}",
+                snapshot.version, VERSION
+            ));
+        }
+        let mut db = Db::new(limits);
+        for (start, end) in snapshot.id_ranges {
+            for id in start..=end {
+                db.add_id(id);
+            }
+        }
+        for (id, client) in snapshot.clients {
+            db.add_client(id, client);
+        }
+        for entry in snapshot.txs {
+            db.add_id(entry.id);
+            db.add_tx(entry.id, entry.tx.parsed_tx, entry.tx.client_id);
+            db.set_tx_state(entry.id, entry.tx.state, entry.tx.held, entry.tx.closed);
+        }
+        Ok(db)
+    }
+}
@@ -0,0 +1,112 @@
+use super::{Snapshot, Transaction, TxState};
+use crate::engine::{Amount, Client};
+
+/// Storage of clients and disputable txs used by the engine
+pub trait Store {
+    /// True if a deposit or withdrawal with this id has been seen
+    fn has_id(&self, id: u32) -> bool;
+
+    fn add_id(&mut self, id: u32);
+
+    /// Record an applied tx so that it can be disputed
+    fn add_tx(&mut self, id: u32, tx: crate::engine::Transaction, client_id: u16);
+
+    /// Update a stored tx after a dispute, resolve or chargeback, see Transaction::next
+    fn set_tx_state(
+        &mut self,
+        id: u32,
+        state: TxState,
+        held: Option<Amount>,
+        closed: Option<Amount>,
+    );
+
+    fn get_tx(&self, id: u32) -> Option<&Transaction>;
+
+    fn add_client(&mut self, id: u16, client: Client);
+
+    fn get_client(&self, id: u16) -> Option<&Client>;
+
+    fn get_client_mut(&mut self, id: u16) -> Option<&mut Client>;
+
+    /// Every client in no particular order
+    fn get_clients(&self) -> Box<dyn Iterator<Item = (u16, &Client)> + '_>;
+
+    /// Copy of the whole state
+    fn snapshot(&self) -> Snapshot;
+
+    /// Called after every input row, the changes made up to line must survive a restart. input
+    /// identifies the rows up to line, see parser::hash_row
+    fn commit(&mut self, _line: u64, _input: u64) -> std::io::Result<()> {
+        Ok(())
+    }
+
+    /// Last line committed, rows up to it have already been processed
+    fn committed(&self) -> u64 {
+        0
+    }
+
+    /// Identity of the rows up to the last line committed, 0 if unknown
+    fn committed_input(&self) -> u64 {
+        0
+    }
+}
+
+impl<S: Store + ?Sized> Store for Box<S> {
+    fn has_id(&self, id: u32) -> bool {
+        (**self).has_id(id)
+    }
+
+    fn add_id(&mut self, id: u32) {
+        (**self).add_id(id)
+    }
+
+    fn add_tx(&mut self, id: u32, tx: crate::engine::Transaction, client_id: u16) {
+        (**self).add_tx(id, tx, client_id)
+    }
+
+    fn set_tx_state(
+        &mut self,
+        id: u32,
+        state: TxState,
+        held: Option<Amount>,
+        closed: Option<Amount>,
+    ) {
+        (**self).set_tx_state(id, state, held, closed)
+    }
+
+    fn get_tx(&self, id: u32) -> Option<&Transaction> {
+        (**self).get_tx(id)
+    }
+
+    fn add_client(&mut self, id: u16, client: Client) {
+        (**self).add_client(id, client)
+    }
+
+    fn get_client(&self, id: u16) -> Option<&Client> {
+        (**self).get_client(id)
+    }
+
+    fn get_client_mut(&mut self, id: u16) -> Option<&mut Client> {
+        (**self).get_client_mut(id)
+    }
+
+    fn get_clients(&self) -> Box<dyn Iterator<Item = (u16, &Client)> + '_> {
+        (**self).get_clients()
+    }
+
+    fn snapshot(&self) -> Snapshot {
+        (**self).snapshot()
+    }
+
+    fn commit(&mut self, line: u64, input: u64) -> std::io::Result<()> {
+        (**self).commit(line, input)
+    }
+
+    fn committed(&self) -> u64 {
+        (**self).committed()
+    }
+
+    fn committed_input(&self) -> u64 {
+        (**self).committed_input()
+    }
+}
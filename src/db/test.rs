@@ -0,0 +1,449 @@
+use super::{Limits, Store, TxState};
+use crate::engine::{Amount, Currency, RejectReason, Transaction};
+
+fn one() -> Amount {
+    Amount::parse(Currency::Usd, "1").unwrap()
+}
+
+fn minus_one() -> Amount {
+    Amount::parse(Currency::Usd, "-1").unwrap()
+}
+
+fn dispute() -> Transaction {
+    Transaction::new_dispute(minus_one())
+}
+
+fn resolve() -> Transaction {
+    Transaction::new_resolve(one())
+}
+
+fn chargeback() -> Transaction {
+    Transaction::new_chargeback(minus_one())
+}
+
+// A full deposit of 1 in state, the amounts follow from the state
+fn stored(state: TxState) -> super::Transaction {
+    super::Transaction {
+        state,
+        ..stored_deposit()
+    }
+}
+
+fn next_state(state: TxState, tx: &Transaction) -> Result<TxState, RejectReason> {
+    stored(state).next(tx).map(|next| next.state)
+}
+
+#[test]
+fn processed_tx_can_only_be_disputed() {
+    assert_eq!(
+        next_state(TxState::Processed, &dispute()),
+        Ok(TxState::Disputed)
+    );
+    assert_eq!(
+        next_state(TxState::Processed, &resolve()),
+        Err(RejectReason::NotDisputed)
+    );
+    assert_eq!(
+        next_state(TxState::Processed, &chargeback()),
+        Err(RejectReason::NotDisputed)
+    );
+    assert_eq!(
+        next_state(TxState::Processed, &deposit()),
+        Err(RejectReason::NotDisputed)
+    );
+}
+
+#[test]
+fn disputed_tx_can_be_resolved_or_charged_back() {
+    assert_eq!(
+        next_state(TxState::Disputed, &dispute()),
+        Err(RejectReason::AlreadyDisputed)
+    );
+    assert_eq!(
+        next_state(TxState::Disputed, &resolve()),
+        Ok(TxState::Resolved)
+    );
+    assert_eq!(
+        next_state(TxState::Disputed, &chargeback()),
+        Ok(TxState::ChargedBack)
+    );
+}
+
+#[test]
+fn resolved_and_charged_back_txs_are_final() {
+    for state in &[TxState::Resolved, TxState::ChargedBack] {
+        assert_eq!(
+            next_state(*state, &dispute()),
+            Err(RejectReason::DisputeClosed)
+        );
+        assert_eq!(
+            next_state(*state, &resolve()),
+            Err(RejectReason::DisputeClosed)
+        );
+        assert_eq!(
+            next_state(*state, &chargeback()),
+            Err(RejectReason::DisputeClosed)
+        );
+    }
+}
+
+fn usd(amount: &str) -> Amount {
+    Amount::parse(Currency::Usd, amount).unwrap()
+}
+
+#[test]
+fn partial_disputes_are_bounded_by_the_tx_amount() {
+    let tx = stored(TxState::Processed);
+    let tx = tx.next(&Transaction::new_dispute(usd("-0.25"))).unwrap();
+    let tx = tx.next(&Transaction::new_dispute(usd("-0.5"))).unwrap();
+    assert_eq!(tx.held(), usd("0.75"));
+    assert_eq!(
+        tx.next(&Transaction::new_dispute(usd("-0.5"))).unwrap_err(),
+        RejectReason::ExceedsLinkedAmount("0.5000".to_string())
+    );
+    assert_eq!(
+        tx.next(&Transaction::new_resolve(usd("1"))).unwrap_err(),
+        RejectReason::ExceedsLinkedAmount("1.0000".to_string())
+    );
+
+    let tx = tx.next(&Transaction::new_resolve(usd("0.5"))).unwrap();
+    assert_eq!(tx.state, TxState::Disputed);
+    let tx = tx.next(&Transaction::new_chargeback(usd("-0.25"))).unwrap();
+    assert_eq!(tx.state, TxState::ChargedBack);
+    assert!(tx.held().is_zero());
+    assert_eq!(tx.closed(), usd("0.75"));
+    assert_eq!(tx.disputable(), usd("0.25"));
+
+    let tx = tx.next(&Transaction::new_dispute(usd("-0.25"))).unwrap();
+    assert_eq!(
+        tx.next(&Transaction::new_dispute(usd("-0.25")))
+            .unwrap_err(),
+        RejectReason::AlreadyDisputed
+    );
+    let eur = Amount::parse(Currency::Eur, "0.25").unwrap();
+    assert_eq!(
+        tx.next(&Transaction::new_resolve(eur)).unwrap_err(),
+        RejectReason::CurrencyMismatch("EUR".to_string())
+    );
+}
+
+fn deposit() -> Transaction {
+    Transaction::new_deposit(one())
+}
+
+fn retention(retention: usize) -> Limits {
+    Limits {
+        retention: Some(retention),
+        max_memory: None,
+    }
+}
+
+#[test]
+fn unbounded_db_keeps_every_tx() {
+    let mut db = super::Db::new(Limits::default());
+    for id in 0..100 {
+        db.add_tx(id, deposit(), 1);
+    }
+    assert!((0..100).all(|id| db.get_tx(id).is_some()));
+}
+
+#[test]
+fn bounded_db_drops_oldest_txs() {
+    let mut db = super::Db::new(retention(2));
+    db.add_tx(1, deposit(), 1);
+    db.add_tx(2, deposit(), 1);
+    db.add_tx(3, deposit(), 1);
+    assert!(db.get_tx(1).is_none());
+    assert!(db.get_tx(2).is_some());
+    assert!(db.get_tx(3).is_some());
+}
+
+// Empty directory unique for the test
+fn tmp_dir(name: &str) -> std::path::PathBuf {
+    let dir = std::env::temp_dir().join(format!("pinoedino-{}-{}", name, std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    dir
+}
+
+fn client_with_deposit() -> crate::engine::Client {
+    let mut client = crate::engine::Client::new();
+    client.handle_transaction(deposit()).unwrap();
+    client
+}
+
+#[test]
+fn snapshot_round_trip() {
+    let mut db = super::Db::new(Limits::default());
+    db.add_client(1, client_with_deposit());
+    db.add_tx(1, deposit(), 1);
+    db.add_tx(2, deposit(), 1);
+    db.set_tx_state(2, TxState::Disputed, Some(usd("0.5")), None);
+
+    let json = serde_json::to_string(&db.snapshot()).unwrap();
+    let snapshot = serde_json::from_str(&json).unwrap();
+    let restored = super::Db::from_snapshot(snapshot, retention(1)).unwrap();
+
+    assert!(restored.get_tx(1).is_none());
+    assert_eq!(restored.get_tx(2).unwrap().state, TxState::Disputed);
+    assert_eq!(restored.get_tx(2).unwrap().held(), usd("0.5"));
+    assert_eq!(
+        format!("{:?}", restored.get_client(1)),
+        format!("{:?}", db.get_client(1))
+    );
+}
+
+#[test]
+fn snapshot_with_other_version_is_rejected() {
+    let mut snapshot = super::Db::new(Limits::default()).snapshot();
+    snapshot.version += 1;
+    assert!(super::Db::from_snapshot(snapshot, Limits::default()).is_err());
+}
+
+#[test]
+fn durable_db_recovers_committed_changes() {
+    let dir = tmp_dir("recover");
+    {
+        let mut db = super::DurableDb::open(&dir, Limits::default(), 1_000).unwrap();
+        db.add_client(1, client_with_deposit());
+        db.add_tx(1, deposit(), 1);
+        db.commit(2, 0).unwrap();
+        db.set_tx_state(1, TxState::Disputed, None, None);
+        db.add_client(2, client_with_deposit());
+        // Crash before the commit of line 3
+    }
+    let db = super::DurableDb::open(&dir, Limits::default(), 1_000).unwrap();
+    assert_eq!(db.committed(), 2);
+    assert!(db.get_client(1).is_some());
+    assert!(db.get_client(2).is_none());
+    assert_eq!(db.get_tx(1).unwrap().state, TxState::Processed);
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn durable_db_recovers_commits_of_an_older_line() {
+    let dir = tmp_dir("older_line");
+    {
+        let mut db = super::DurableDb::open(&dir, Limits::default(), 1_000).unwrap();
+        db.add_tx(1, deposit(), 1);
+        db.commit(2, 0).unwrap();
+        // A row without position is committed as line 0
+        db.add_tx(2, deposit(), 1);
+        db.commit(0, 0).unwrap();
+    }
+    let db = super::DurableDb::open(&dir, Limits::default(), 1_000).unwrap();
+    assert_eq!(db.committed(), 2);
+    assert!(db.get_tx(1).is_some() && db.get_tx(2).is_some());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn durable_db_ignores_a_torn_last_line() {
+    use std::io::Write;
+    let dir = tmp_dir("torn");
+    {
+        let mut db = super::DurableDb::open(&dir, Limits::default(), 1_000).unwrap();
+        db.add_tx(1, deposit(), 1);
+        db.commit(1, 0).unwrap();
+    }
+    // The last line ends in the middle of a two bytes char
+    let mut wal = std::fs::OpenOptions::new()
+        .append(true)
+        .open(dir.join("wal.jsonl"))
+        .unwrap();
+    wal.write_all(b"{\"client\":{\"id\":2,\"client\":\"\xc3")
+        .unwrap();
+    drop(wal);
+    let db = super::DurableDb::open(&dir, Limits::default(), 1_000).unwrap();
+    assert_eq!(db.committed(), 1);
+    assert!(db.get_tx(1).is_some());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn durable_db_recovers_from_checkpoint_and_wal() {
+    let dir = tmp_dir("checkpoint");
+    {
+        let mut db = super::DurableDb::open(&dir, Limits::default(), 2).unwrap();
+        for id in 1..=5 {
+            db.add_tx(id, deposit(), 1);
+            db.commit(id as u64, 0).unwrap();
+        }
+    }
+    let db = super::DurableDb::open(&dir, Limits::default(), 2).unwrap();
+    assert_eq!(db.committed(), 5);
+    assert!((1..=5).all(|id| db.get_tx(id).is_some()));
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn snapshot_save_and_load() {
+    let mut db = super::Db::new(Limits::default());
+    db.add_client(1, client_with_deposit());
+    db.add_tx(1, deposit(), 1);
+
+    let mut buffer = vec![];
+    db.snapshot().save(&mut buffer).unwrap();
+    let snapshot = super::Snapshot::load(&buffer[..]).unwrap();
+
+    assert_eq!(snapshot.version, super::snapshot::VERSION);
+    assert_eq!(snapshot.clients.len(), 1);
+    assert_eq!(snapshot.txs.len(), 1);
+}
+
+#[test]
+fn version_1_snapshot_reserves_the_ids_of_its_txs() {
+    let mut db = super::Db::new(Limits::default());
+    db.add_tx(1, deposit(), 1);
+    let mut snapshot = db.snapshot();
+    snapshot.version = 1;
+    snapshot.id_ranges = vec![];
+
+    let restored = super::Db::from_snapshot(snapshot, Limits::default()).unwrap();
+    assert!(restored.has_id(1));
+    assert!(!restored.has_id(2));
+}
+
+#[test]
+fn snapshot_stores_consecutive_ids_as_ranges() {
+    let mut db = super::Db::new(Limits::default());
+    for id in (1..=1000).chain(2000..=2001) {
+        db.add_id(id);
+    }
+    let snapshot = db.snapshot();
+    assert_eq!(snapshot.id_ranges, vec![(1, 1000), (2000, 2001)]);
+    let restored = super::Db::from_snapshot(snapshot, Limits::default()).unwrap();
+    assert!(restored.has_id(1000) && restored.has_id(2001));
+    assert!(!restored.has_id(1001));
+}
+
+fn stored_deposit() -> super::Transaction {
+    super::Transaction::new(deposit(), 1)
+}
+
+#[quickcheck]
+fn prop_id_set_behaves_like_a_set(ids: Vec<u32>) -> bool {
+    let mut set = super::IdSet::default();
+    for id in &ids {
+        set.insert(*id);
+    }
+    let mut expected = ids.clone();
+    expected.sort_unstable();
+    expected.dedup();
+    set.len() == expected.len()
+        && set.iter().collect::<Vec<u32>>() == expected
+        && ids.iter().all(|id| set.contains(*id))
+}
+
+#[quickcheck]
+fn prop_id_set_ranges_cover_its_ids(ids: Vec<u32>) -> bool {
+    let mut set = super::IdSet::default();
+    for id in ids {
+        set.insert(id);
+    }
+    let ranges: Vec<(u32, u32)> = set.ranges().collect();
+    let merged = ranges.windows(2).all(|pair| pair[0].1 + 1 < pair[1].0);
+    let expanded: Vec<u32> = ranges
+        .into_iter()
+        .flat_map(|(start, end)| start..=end)
+        .collect();
+    merged && expanded == set.iter().collect::<Vec<u32>>()
+}
+
+#[test]
+fn id_set_covers_the_whole_range() {
+    let mut set = super::IdSet::default();
+    set.insert(0);
+    set.insert(u32::MAX);
+    assert!(set.contains(0) && set.contains(u32::MAX));
+    assert!(!set.contains(1) && !set.contains(u32::MAX - 1));
+    assert_eq!(set.memory(), 2 * 8 * 1024);
+}
+
+// Ids spread over the whole u32 range
+fn scattered(id: u32) -> u32 {
+    id.wrapping_mul(2_654_435_761)
+}
+
+#[test]
+fn tx_index_drops_oldest_page_over_max_memory() {
+    let page_memory = super::TxIndex::page_memory();
+    let mut index = super::TxIndex::new(Some(2 * page_memory));
+    // More than an eighth of a page makes it dense
+    for page in 0..2 {
+        for id in 0..1000 {
+            index.insert(page * 4096 + id, stored_deposit());
+        }
+    }
+    index.insert(1000, stored_deposit());
+    assert_eq!(index.len(), 2001);
+    assert_eq!(index.memory(), 2 * page_memory);
+    index.insert(8192, stored_deposit());
+
+    assert!(index.get(0).is_none() && index.get(1000).is_none());
+    assert!(index.get(4096).is_some() && index.get(8192).is_some());
+    assert_eq!(index.len(), 1001);
+    assert!(index.memory() <= 2 * page_memory);
+}
+
+#[test]
+fn tx_index_keeps_scattered_ids_in_sparse_pages() {
+    let mut index = super::TxIndex::new(None);
+    for id in 0..1000 {
+        index.insert(scattered(id), stored_deposit());
+    }
+    assert_eq!(index.len(), 1000);
+    assert!((0..1000).all(|id| index.get(scattered(id)).is_some()));
+    assert!(index.memory() < 10 * super::TxIndex::page_memory());
+}
+
+#[test]
+fn tx_index_bounds_scattered_ids_by_max_memory() {
+    let max_memory = super::TxIndex::page_memory() / 8;
+    let mut index = super::TxIndex::new(Some(max_memory));
+    for id in 0..10_000 {
+        index.insert(scattered(id), stored_deposit());
+        assert!(index.memory() <= max_memory);
+    }
+    assert!(index.get(scattered(9_999)).is_some());
+    assert!(index.get(scattered(0)).is_none());
+    assert_eq!(index.len(), index.iter().count());
+}
+
+#[quickcheck]
+fn prop_tx_index_behaves_like_a_map(ops: Vec<(u16, bool, u16)>) -> bool {
+    let mut index = super::TxIndex::new(None);
+    let mut map = std::collections::HashMap::new();
+    for (id, insert, client_id) in ops {
+        // Few pages with many txs so that some of them become dense
+        let id = (id % 3) as u32 * 4096 + id as u32 % 1024;
+        if insert {
+            let tx = super::Transaction::new(deposit(), client_id);
+            index.insert(id, tx);
+            map.insert(id, client_id);
+        } else if index.remove(id).map(|tx| tx.client_id) != map.remove(&id) {
+            return false;
+        }
+    }
+    let mut ids: Vec<(u32, u16)> = index.iter().map(|(id, tx)| (id, tx.client_id)).collect();
+    ids.sort_unstable();
+    let mut expected: Vec<(u32, u16)> = map.into_iter().collect();
+    expected.sort_unstable();
+    index.len() == expected.len()
+        && ids == expected
+        && expected
+            .iter()
+            .all(|(id, client_id)| index.get(*id).map(|tx| tx.client_id) == Some(*client_id))
+}
+
+#[test]
+fn tx_index_frees_empty_pages() {
+    let mut index = super::TxIndex::new(None);
+    index.insert(10, stored_deposit());
+    index.insert(11, stored_deposit());
+    assert!(index.remove(10).is_some());
+    assert!(index.remove(10).is_none());
+    assert!(index.memory() > 0);
+    assert!(index.remove(11).is_some());
+    assert_eq!(index.memory(), 0);
+    assert_eq!(index.iter().count(), 0);
+}
@@ -1,202 +1,272 @@
+use super::currency::{Amount, Currency};
+use super::error::RejectReason;
+use super::policy::FrozenPolicy;
 use super::transaction::Transaction;
-use super::utils;
-use super::CreditUsd;
-use super::DebtUsd;
-use super::FixedToInt;
-use super::Usd;
-use super::{zero_usd, zero_usd_as_credit};
-
-lazy_static! {
-    static ref ZERO: Usd = super::zero_usd();
-    static ref C_ZERO: CreditUsd = super::zero_usd_as_credit();
-}
+use serde::{Deserialize, Serialize};
+use std::collections::BTreeMap;
 
-/// Overflow used only for cashbacks if a tx try to withdrawl more than avaible it just fail
-#[derive(Debug)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct Client {
+    /// Set by a chargeback or a freeze, only the operations allowed by FrozenPolicy are applied
     pub locked: bool,
-    pub total: CreditUsd,
-    pub held: Option<DebtUsd>,
-    pub overflow: Option<DebtUsd>,
+    /// A closed account never accepts operations again
+    #[serde(default)]
+    pub closed: bool,
+    /// Why the account has been locked or closed
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub lock_reason: Option<String>,
+    /// Reason of the last freeze, unfreeze or close, kept after an unfreeze
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub admin_reason: Option<String>,
+    pub balances: BTreeMap<Currency, Balance>,
+}
+
+/// Only a chargeback can make total < 0, the client is then in debt and the next deposits pay
+/// it down first. A withdrawal bigger than available just fail, held is always >= 0
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(from = "BalanceRepr")]
+pub struct Balance {
+    pub total: Amount,
+    pub held: Option<Amount>,
+}
+
+// Before negative totals a chargeback bigger than total was stored in overflow with total 0
+#[derive(Deserialize)]
+struct BalanceRepr {
+    total: Amount,
+    held: Option<Amount>,
+    #[serde(default)]
+    overflow: Option<Amount>,
+}
+
+impl From<BalanceRepr> for Balance {
+    fn from(repr: BalanceRepr) -> Self {
+        let total = match repr.overflow {
+            None => repr.total,
+            Some(overflow) => (overflow + repr.total).unwrap_or(repr.total),
+        };
+        Balance {
+            total,
+            held: repr.held,
+        }
+    }
 }
 
 impl Client {
     pub fn new() -> Self {
         Client {
             locked: false,
-            total: *C_ZERO,
+            closed: false,
+            lock_reason: None,
+            admin_reason: None,
+            balances: BTreeMap::new(),
+        }
+    }
+
+    /// Nothing is applied to a locked account
+    pub fn handle_transaction(&mut self, transaction: Transaction) -> Result<(), RejectReason> {
+        self.handle_transaction_with(transaction, FrozenPolicy::default())
+    }
+
+    pub fn handle_transaction_with(
+        &mut self,
+        transaction: Transaction,
+        frozen: FrozenPolicy,
+    ) -> Result<(), RejectReason> {
+        if self.closed {
+            return Err(RejectReason::AccountClosed);
+        }
+        if self.locked && !frozen.allows(&transaction) {
+            return Err(RejectReason::AccountLocked);
+        }
+        let currency = transaction.get_amount().currency();
+        let balance = self
+            .balances
+            .entry(currency)
+            .or_insert_with(|| Balance::new(currency));
+        let result = match transaction {
+            Transaction::Deposit(amount) => balance.deposit(amount),
+            Transaction::Withdrawal(amount) => balance.withdrawal(amount),
+            Transaction::Dispute(amount) if amount.is_debt() => balance.held(amount),
+            Transaction::Dispute(amount) => balance.held_withdrawal(amount),
+            Transaction::Resolve(amount) if amount.is_debt() => balance.release_withdrawal(amount),
+            Transaction::Resolve(amount) => balance.release(amount),
+            Transaction::Chargeback(amount) if amount.is_debt() => balance.chargeback(amount),
+            Transaction::Chargeback(amount) => balance.chargeback_withdrawal(amount),
+        };
+        if let (Ok(()), Transaction::Chargeback(_)) = (&result, transaction) {
+            self.locked = true;
+            self.lock_reason = Some("chargeback".to_string());
+        }
+        result
+    }
+
+    /// Lock the account, freezing a frozen account only updates the reason
+    pub fn freeze(&mut self, reason: String) -> Result<(), RejectReason> {
+        if self.closed {
+            return Err(RejectReason::AccountClosed);
+        }
+        self.locked = true;
+        self.lock_reason = Some(reason.clone());
+        self.admin_reason = Some(reason);
+        Ok(())
+    }
+
+    /// Unlock a frozen or charged back account
+    pub fn unfreeze(&mut self, reason: String) -> Result<(), RejectReason> {
+        if self.closed {
+            return Err(RejectReason::AccountClosed);
+        }
+        self.locked = false;
+        self.lock_reason = None;
+        self.admin_reason = Some(reason);
+        Ok(())
+    }
+
+    /// Lock the account forever, the balances are kept
+    pub fn close(&mut self, reason: String) -> Result<(), RejectReason> {
+        if self.closed {
+            return Err(RejectReason::AccountClosed);
+        }
+        self.locked = true;
+        self.closed = true;
+        self.lock_reason = Some(reason.clone());
+        self.admin_reason = Some(reason);
+        Ok(())
+    }
+
+    #[cfg(test)]
+    pub fn new_(locked: bool, balance: Balance) -> Self {
+        let mut balances = BTreeMap::new();
+        balances.insert(balance.total.currency(), balance);
+        Client {
+            locked,
+            closed: false,
+            lock_reason: None,
+            admin_reason: None,
+            balances,
+        }
+    }
+}
+
+impl Balance {
+    pub fn new(currency: Currency) -> Self {
+        Balance {
+            total: Amount::zero(currency),
             held: None,
-            overflow: None,
-        }
-    }
-
-    pub fn handle_transaction(&mut self, transaction: Transaction) {
-        if self.locked {
-            return ();
-        }
-        match transaction {
-            Transaction::Deposit(usd) => match self.deposit(usd) {
-                Some(()) => (),
-                None => utils::print_warning(&transaction, &self),
-            },
-            Transaction::Withdrawal(usd) => match self.withdrawal(usd) {
-                Some(()) => (),
-                None => utils::print_warning(&transaction, &self),
-            },
-            Transaction::Dispute(usd) => match self.held(usd) {
-                Some(()) => (),
-                None => utils::print_warning(&transaction, &self),
-            },
-            Transaction::Resolve(usd) => match self.release(usd) {
-                Some(()) => (),
-                None => utils::print_warning(&transaction, &self),
-            },
-            Transaction::Chargeback(usd) => match self.chargeback(usd) {
-                Some(()) => (),
-                None => utils::print_warning(&transaction, &self),
-            },
-        }
-    }
-
-    pub fn avaiable_amount(&self) -> CreditUsd {
+        }
+    }
+
+    pub fn avaiable_amount(&self) -> Amount {
         match self.held {
             None => self.total,
-            Some(held) => match (self.total - held).expect("impossible state") {
-                merx::Asset::Debt(_) => panic!("impossible state"),
-                merx::Asset::Credit(x) => x,
-            },
+            Some(held) => (self.total - held).expect("impossible state"),
         }
     }
 
-    fn avaiable_amount_from_new(&self, new_total: CreditUsd) -> Usd {
+    fn avaiable_amount_from_new(&self, new_total: Amount) -> Result<Amount, RejectReason> {
         match self.held {
-            None => merx::Asset::Credit(new_total),
-            Some(held) => (new_total - held).expect("impossible state"),
+            None => Ok(new_total),
+            Some(held) => (new_total - held).ok_or(RejectReason::Overflow),
         }
     }
 
-    fn deposit(&mut self, usd: CreditUsd) -> Option<()> {
-        let new_amount = (self.total + usd)?;
-        self.total = new_amount;
-        Some(())
+    fn deposit(&mut self, amount: Amount) -> Result<(), RejectReason> {
+        let new_total = (self.total + amount).ok_or(RejectReason::Overflow)?;
+        self.total = new_total;
+        Ok(())
     }
 
-    fn withdrawal(&mut self, usd: DebtUsd) -> Option<()> {
-        let new_total = (self.total - usd)?;
+    fn withdrawal(&mut self, amount: Amount) -> Result<(), RejectReason> {
+        let new_total = (self.total + amount).ok_or(RejectReason::Overflow)?;
 
         // Check if required amount is bigger than total amount
-        match new_total {
-            merx::Asset::Debt(_) => None,
-            merx::Asset::Credit(new_total) => {
-                // Check if required amount is bigger than (total amount + held amount)
-                match self.avaiable_amount_from_new(new_total) {
-                    merx::Asset::Debt(_) => None,
-                    merx::Asset::Credit(_) => {
-                        self.total = new_total;
-                        Some(())
-                    }
-                }
-            }
-        }
-    }
-
-    fn unwrap_held_or_0(&self) -> Usd {
+        if new_total.is_debt() {
+            return Err(RejectReason::InsufficientFunds);
+        }
+        // Check if required amount is bigger than (total amount - held amount)
+        if self.avaiable_amount_from_new(new_total)?.is_debt() {
+            return Err(RejectReason::InsufficientFunds);
+        }
+        self.total = new_total;
+        Ok(())
+    }
+
+    fn unwrap_held_or_0(&self) -> Amount {
         match self.held {
-            None => zero_usd(),
-            Some(held) => merx::Asset::Debt(held),
+            None => Amount::zero(self.total.currency()),
+            Some(held) => held,
         }
     }
 
-    fn held(&mut self, usd: DebtUsd) -> Option<()> {
-        let new_held = (self.unwrap_held_or_0() + merx::Asset::Debt(usd))?;
-        match new_held {
-            merx::Asset::Credit(_) => panic!("impossible state"),
-            merx::Asset::Debt(new_held) => {
-                let avaiable = (self.total - new_held)?;
-                match avaiable {
-                    merx::Asset::Debt(_) => {
-                        //self.overflow = Some(overflow);
-                        self.held = Some(new_held);
-                        Some(())
-                    }
-                    merx::Asset::Credit(_) => {
-                        self.held = Some(new_held);
-                        Some(())
-                    }
-                }
-            }
+    // amount is the disputed deposit with the sign inverted, with a negative total available
+    // can become too small to be represented
+    fn held(&mut self, amount: Amount) -> Result<(), RejectReason> {
+        let new_held = (self.unwrap_held_or_0() - amount).ok_or(RejectReason::Overflow)?;
+        (self.total - new_held).ok_or(RejectReason::Overflow)?;
+        self.held = Some(new_held);
+        Ok(())
+    }
+
+    fn release(&mut self, amount: Amount) -> Result<(), RejectReason> {
+        let held = self.held.ok_or(RejectReason::InsufficientHeld)?;
+        let new_held = (held - amount).ok_or(RejectReason::Overflow)?;
+        // If new held is 0 this is fine
+        // If is less than 0 we are trying to release more found
+        // thane the ones helded and this is must be an error
+        if new_held.is_debt() {
+            Err(RejectReason::InsufficientHeld)
+        } else if new_held.is_zero() {
+            self.held = None;
+            Ok(())
+        } else {
+            self.held = Some(new_held);
+            Ok(())
         }
     }
 
-    fn release(&mut self, usd: CreditUsd) -> Option<()> {
-        match self.held {
-            None => None,
-            Some(held) => match (held + usd)? {
-                merx::Asset::Debt(new_held) => {
-                    self.held = Some(new_held);
-                    Some(())
-                }
-                // If new held is a credit can be 0 and this is fine
-                // If is bigger than 0 we are trying to release more found
-                // thane the ones helded and this is must be an error
-                merx::Asset::Credit(held) => {
-                    if merx::Asset::Credit(held).to_int() == 0 {
-                        self.held = None;
-                        // If held is 0 overflow must be 0
-                        // self.overflow = None;
-                        Some(())
-                    } else {
-                        None
-                    }
-                }
-            },
-        }
-    }
-
-    fn update_total(&mut self, chargeback: DebtUsd) -> Option<()> {
-        match (self.total - chargeback)? {
-            merx::Asset::Debt(overflow) => {
-                self.overflow = Some(overflow);
-                self.total = zero_usd_as_credit();
-                Some(())
-            }
-            merx::Asset::Credit(new_total) => {
-                self.total = new_total;
-                Some(())
-            }
-        }
-    }
-
-    fn chargeback(&mut self, usd: DebtUsd) -> Option<()> {
-        let usd_to_release = (merx::Asset::Debt(usd) * -1).expect("impossible");
-        self.locked = true;
-        match usd_to_release {
-            merx::Asset::Credit(usd_to_release) => {
-                match self.release(usd_to_release) {
-                    Some(_) => (),
-                    None => {
-                        eprintln!("WARNING: chargeback bigger than dispute");
-                        self.held = None
-                    }
-                };
-                self.update_total(usd);
-                Some(())
-            }
-            _ => panic!("impossible"),
+    // total can become < 0
+    fn update_total(&mut self, chargeback: Amount) -> Result<(), RejectReason> {
+        self.total = (self.total + chargeback).ok_or(RejectReason::Overflow)?;
+        Ok(())
+    }
+
+    fn chargeback(&mut self, amount: Amount) -> Result<(), RejectReason> {
+        let to_release = amount.neg().ok_or(RejectReason::Overflow)?;
+        // A chargeback bigger than the dispute release everything
+        if self.release(to_release).is_err() {
+            self.held = None
+        };
+        self.update_total(amount)
+    }
+
+    // A disputed withdrawal is provisionally given back to the client as held funds
+    fn held_withdrawal(&mut self, amount: Amount) -> Result<(), RejectReason> {
+        let new_total = (self.total + amount).ok_or(RejectReason::Overflow)?;
+        let new_held = (self.unwrap_held_or_0() + amount).ok_or(RejectReason::Overflow)?;
+        self.total = new_total;
+        self.held = Some(new_held);
+        Ok(())
+    }
+
+    // A resolved withdrawal dispute confirm the withdrawal so held funds leave the account
+    fn release_withdrawal(&mut self, amount: Amount) -> Result<(), RejectReason> {
+        let new_total = (self.total + amount).ok_or(RejectReason::Overflow)?;
+        if new_total.is_debt() {
+            return Err(RejectReason::InsufficientFunds);
         }
+        self.release(amount.neg().ok_or(RejectReason::Overflow)?)?;
+        self.total = new_total;
+        Ok(())
     }
+
+    // A charged back withdrawal credit the account so held funds become available
+    fn chargeback_withdrawal(&mut self, amount: Amount) -> Result<(), RejectReason> {
+        self.release(amount)
+    }
+
     #[cfg(test)]
-    pub fn new_(
-        locked: bool,
-        total: CreditUsd,
-        held: Option<DebtUsd>,
-        overflow: Option<DebtUsd>,
-    ) -> Self {
-        Client {
-            locked,
-            total,
-            held,
-            overflow,
-        }
+    pub fn new_(total: Amount, held: Option<Amount>) -> Self {
+        Balance { total, held }
     }
 }
@@ -0,0 +1,196 @@
+use super::{Eur, FixedToInt, Gbp, Usd};
+use merx::fixed::IsFixed;
+use serde::{Deserialize, Serialize};
+use std::convert::TryFrom;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize)]
+#[serde(rename_all = "UPPERCASE")]
+pub enum Currency {
+    Usd,
+    Eur,
+    Gbp,
+}
+
+impl Currency {
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Usd => "USD",
+            Self::Eur => "EUR",
+            Self::Gbp => "GBP",
+        }
+    }
+
+    /// Decimal digits of the asset, it must match the precision passed to new_asset!
+    pub fn decimals(&self) -> u32 {
+        match self {
+            Self::Usd => 4,
+            Self::Eur => 4,
+            Self::Gbp => 4,
+        }
+    }
+}
+
+impl Default for Currency {
+    fn default() -> Self {
+        Self::Usd
+    }
+}
+
+impl FromStr for Currency {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match &s.to_ascii_uppercase()[..] {
+            "USD" => Ok(Self::Usd),
+            "EUR" => Ok(Self::Eur),
+            "GBP" => Ok(Self::Gbp),
+            _ => Err(format!("unknown currency: {}", s)),
+        }
+    }
+}
+
+impl std::fmt::Display for Currency {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        write!(f, "{}", self.code())
+    }
+}
+
+/// An amount of one of the supported assets, operations between amounts of different
+/// currencies always fail
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(into = "AmountRepr", try_from = "AmountRepr")]
+pub enum Amount {
+    Usd(Usd),
+    Eur(Eur),
+    Gbp(Gbp),
+}
+
+// Evaluate $body with $x bound to the inner asset of $amount
+macro_rules! with_asset {
+    ($amount:expr, $x:ident => $body:expr) => {
+        match $amount {
+            Amount::Usd($x) => $body,
+            Amount::Eur($x) => $body,
+            Amount::Gbp($x) => $body,
+        }
+    };
+}
+
+// Like with_asset but $body return an Option of an asset of the same currency of $amount
+macro_rules! map_asset {
+    ($amount:expr, $x:ident => $body:expr) => {
+        match $amount {
+            Amount::Usd($x) => $body.map(Amount::Usd),
+            Amount::Eur($x) => $body.map(Amount::Eur),
+            Amount::Gbp($x) => $body.map(Amount::Gbp),
+        }
+    };
+}
+
+impl Amount {
+    pub fn zero(currency: Currency) -> Self {
+        match currency {
+            Currency::Usd => Amount::Usd(Usd::try_from(0).unwrap()),
+            Currency::Eur => Amount::Eur(Eur::try_from(0).unwrap()),
+            Currency::Gbp => Amount::Gbp(Gbp::try_from(0).unwrap()),
+        }
+    }
+
+    /// Parse a decimal string, None if it is not a valid amount for currency
+    pub fn parse(currency: Currency, amount: &str) -> Option<Self> {
+        match currency {
+            Currency::Usd => Usd::try_from(amount).ok().map(Amount::Usd),
+            Currency::Eur => Eur::try_from(amount).ok().map(Amount::Eur),
+            Currency::Gbp => Gbp::try_from(amount).ok().map(Amount::Gbp),
+        }
+    }
+
+    pub fn currency(&self) -> Currency {
+        match self {
+            Amount::Usd(_) => Currency::Usd,
+            Amount::Eur(_) => Currency::Eur,
+            Amount::Gbp(_) => Currency::Gbp,
+        }
+    }
+
+    /// True for values < 0
+    pub fn is_debt(&self) -> bool {
+        with_asset!(self, x => matches!(x, merx::Asset::Debt(_)))
+    }
+
+    pub fn is_zero(&self) -> bool {
+        with_asset!(self, x => x.to_int() == 0)
+    }
+
+    pub fn neg(self) -> Option<Self> {
+        map_asset!(self, x => (x * -1))
+    }
+
+    pub fn to_parts(&self) -> (i128, i128, u128) {
+        with_asset!(self, x => x.get_inner().to_parts())
+    }
+}
+
+impl std::ops::Add for Amount {
+    type Output = Option<Amount>;
+
+    fn add(self, other: Amount) -> Option<Amount> {
+        match (self, other) {
+            (Amount::Usd(a), Amount::Usd(b)) => (a + b).map(Amount::Usd),
+            (Amount::Eur(a), Amount::Eur(b)) => (a + b).map(Amount::Eur),
+            (Amount::Gbp(a), Amount::Gbp(b)) => (a + b).map(Amount::Gbp),
+            (_, _) => None,
+        }
+    }
+}
+
+impl std::ops::Sub for Amount {
+    type Output = Option<Amount>;
+
+    fn sub(self, other: Amount) -> Option<Amount> {
+        self + other.neg()?
+    }
+}
+
+/// Decimal representation with all the digits of the currency eg "-1.5000"
+impl std::fmt::Display for Amount {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        // Integer and fractional parts have the same sign
+        let (integer, fraction, _) = self.to_parts();
+        let sign = if self.is_debt() { "-" } else { "" };
+        write!(
+            f,
+            "{}{}.{:0width$}",
+            sign,
+            integer.abs(),
+            fraction.abs(),
+            width = self.currency().decimals() as usize
+        )
+    }
+}
+
+// Serialized form of Amount eg {"currency": "USD", "value": "-1.5000"}
+#[derive(Serialize, Deserialize)]
+struct AmountRepr {
+    currency: Currency,
+    value: String,
+}
+
+impl From<Amount> for AmountRepr {
+    fn from(amount: Amount) -> Self {
+        AmountRepr {
+            currency: amount.currency(),
+            value: amount.to_string(),
+        }
+    }
+}
+
+impl TryFrom<AmountRepr> for Amount {
+    type Error = String;
+
+    fn try_from(repr: AmountRepr) -> Result<Self, String> {
+        Amount::parse(repr.currency, &repr.value)
+            .ok_or_else(|| format!("invalid amount: {}", repr.value))
+    }
+}
@@ -0,0 +1,109 @@
+use std::fmt;
+
+/// Why a row has not been applied
+#[derive(Debug, Clone, PartialEq)]
+pub enum RejectReason {
+    /// The row can not be deserialized
+    Malformed(String),
+    UnknownType(String),
+    UnknownCurrency(String),
+    MissingAmount,
+    InvalidAmount(String),
+    NegativeAmount(String),
+    /// A deposit or withdrawal with an id already used
+    DuplicateTx(u32),
+    /// The linked tx does not exist or is not disputable
+    UnknownLinkedTx(u32),
+    /// The linked tx belongs to another client
+    ClientMismatch(u16),
+    AlreadyDisputed,
+    /// The amount of a dispute, resolve or chargeback is more than what is left of the linked
+    /// tx
+    ExceedsLinkedAmount(String),
+    /// The currency of the amount is not the one of the linked tx
+    CurrencyMismatch(String),
+    NotDisputed,
+    /// The linked tx has already been resolved or charged back
+    DisputeClosed,
+    InsufficientFunds,
+    /// Release more than the held amount
+    InsufficientHeld,
+    AccountLocked,
+    AccountClosed,
+    /// A transfer row without to_client
+    MissingToClient,
+    /// A transfer whose to_client is its client
+    SelfTransfer,
+    /// An admin row without reason
+    MissingReason,
+    Overflow,
+}
+
+impl RejectReason {
+    /// Stable machine readable code
+    pub fn code(&self) -> &'static str {
+        match self {
+            Self::Malformed(_) => "malformed",
+            Self::UnknownType(_) => "unknown_type",
+            Self::UnknownCurrency(_) => "unknown_currency",
+            Self::MissingAmount => "missing_amount",
+            Self::InvalidAmount(_) => "invalid_amount",
+            Self::NegativeAmount(_) => "negative_amount",
+            Self::DuplicateTx(_) => "duplicate_tx",
+            Self::UnknownLinkedTx(_) => "unknown_linked_tx",
+            Self::ClientMismatch(_) => "client_mismatch",
+            Self::AlreadyDisputed => "already_disputed",
+            Self::ExceedsLinkedAmount(_) => "exceeds_linked_amount",
+            Self::CurrencyMismatch(_) => "currency_mismatch",
+            Self::NotDisputed => "not_disputed",
+            Self::DisputeClosed => "dispute_closed",
+            Self::InsufficientFunds => "insufficient_funds",
+            Self::InsufficientHeld => "insufficient_held",
+            Self::AccountLocked => "account_locked",
+            Self::AccountClosed => "account_closed",
+            Self::MissingToClient => "missing_to_client",
+            Self::SelfTransfer => "self_transfer",
+            Self::MissingReason => "missing_reason",
+            Self::Overflow => "overflow",
+        }
+    }
+}
+
+impl fmt::Display for RejectReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Self::Malformed(e) => write!(f, "malformed row: {}", e),
+            Self::UnknownType(type_) => write!(f, "unknown type: {}", type_),
+            Self::UnknownCurrency(currency) => write!(f, "unknown currency: {}", currency),
+            Self::MissingAmount => write!(f, "no amount"),
+            Self::InvalidAmount(amount) => write!(f, "invalid amount: {}", amount),
+            Self::NegativeAmount(amount) => write!(f, "negative amount: {}", amount),
+            Self::DuplicateTx(tx) => write!(f, "duplicate tx id: {}", tx),
+            Self::UnknownLinkedTx(tx) => write!(f, "invalid linked tx id: {}", tx),
+            Self::ClientMismatch(client) => {
+                write!(f, "linked tx belongs to client: {}", client)
+            }
+            Self::AlreadyDisputed => write!(f, "linked tx already disputed"),
+            Self::ExceedsLinkedAmount(amount) => {
+                write!(
+                    f,
+                    "amount exceeds what is left of the linked tx: {}",
+                    amount
+                )
+            }
+            Self::CurrencyMismatch(currency) => {
+                write!(f, "currency differs from the linked tx: {}", currency)
+            }
+            Self::NotDisputed => write!(f, "linked tx not disputed"),
+            Self::DisputeClosed => write!(f, "linked tx dispute already closed"),
+            Self::InsufficientFunds => write!(f, "insufficient funds"),
+            Self::InsufficientHeld => write!(f, "insufficient held funds"),
+            Self::AccountLocked => write!(f, "account locked"),
+            Self::AccountClosed => write!(f, "account closed"),
+            Self::MissingToClient => write!(f, "no destination client"),
+            Self::SelfTransfer => write!(f, "destination is the source client"),
+            Self::MissingReason => write!(f, "no reason"),
+            Self::Overflow => write!(f, "amount overflow"),
+        }
+    }
+}
@@ -1,47 +1,69 @@
 mod client;
+mod currency;
+mod error;
+mod policy;
 #[cfg(test)]
 mod test;
 mod transaction;
-mod utils;
 
-pub use client::Client;
+pub use client::{Balance, Client};
+pub use currency::{Amount, Currency};
+pub use error::RejectReason;
+pub use policy::{DisputePolicy, DuplicatePolicy, FrozenPolicy};
 pub use transaction::Transaction;
 
-use merx::{get_fixed, get_traits, new_asset, Asset, Credit, Debt};
+use merx::{get_fixed, get_traits, new_asset, Asset};
 
 get_traits!();
 
-// Create a new asset called usd with 5 decimal digits precision and a maximum value of
-// 90_000_000_000_000
+// Create the assets usd, eur and gbp with 4 decimal digits precision and a maximum value of
+// 14_000_000_000_000
 new_asset!(usd, 4, 14_000_000_000_000);
+new_asset!(eur, 4, 14_000_000_000_000);
+new_asset!(gbp, 4, 14_000_000_000_000);
 
 pub type Usd = Asset<usd::Value>;
-/// Values < 0
-pub type DebtUsd = Debt<usd::Value>;
-/// Values >= 0
-pub type CreditUsd = Credit<usd::Value>;
+pub type Eur = Asset<eur::Value>;
+pub type Gbp = Asset<gbp::Value>;
 
-pub fn zero_usd() -> Usd {
-    Usd::try_from(0).unwrap()
-}
-
-pub fn zero_usd_as_credit() -> CreditUsd {
-    match Usd::try_from(0).unwrap() {
-        merx::Asset::Credit(x) => x,
-        _ => panic!("impossible"),
+/// States of from and to after moving amount as a withdrawal and a deposit, None is a client
+/// never seen. Err if any of the two fails, locked accounts are rejected whatever the
+/// FrozenPolicy
+pub(crate) fn transfer(
+    from: Option<&Client>,
+    to: Option<&Client>,
+    amount: Amount,
+) -> Result<(Client, Client), RejectReason> {
+    let withdrawal = Transaction::new_withdrawl(amount.neg().ok_or(RejectReason::Overflow)?);
+    let mut from = from.cloned().unwrap_or_else(Client::new);
+    let mut to = to.cloned().unwrap_or_else(Client::new);
+    for client in [&from, &to].iter() {
+        if client.closed {
+            return Err(RejectReason::AccountClosed);
+        }
+        if client.locked {
+            return Err(RejectReason::AccountLocked);
+        }
     }
+    from.handle_transaction(withdrawal)?;
+    to.handle_transaction(Transaction::new_deposit(amount))?;
+    Ok((from, to))
 }
 
-pub fn engine(db: &mut crate::db::Db, transaction: Transaction, client_id: u16) {
-    let client = db.get_client(client_id);
+pub(crate) fn engine<S: crate::db::Store>(
+    db: &mut S,
+    transaction: Transaction,
+    client_id: u16,
+    frozen: FrozenPolicy,
+) -> Result<(), RejectReason> {
+    let client = db.get_client_mut(client_id);
     match client {
         None => {
             let mut client = client::Client::new();
-            client.handle_transaction(transaction);
+            let result = client.handle_transaction_with(transaction, frozen);
             db.add_client(client_id, client);
+            result
         }
-        Some(client) => {
-            client.handle_transaction(transaction);
-        }
+        Some(client) => client.handle_transaction_with(transaction, frozen),
     }
 }
@@ -0,0 +1,117 @@
+use super::transaction::Transaction;
+use std::str::FromStr;
+
+/// Kinds of stored transactions that can be disputed
+#[derive(Debug, Clone, Copy)]
+pub struct DisputePolicy {
+    pub deposits: bool,
+    pub withdrawals: bool,
+}
+
+impl DisputePolicy {
+    pub fn is_disputable(&self, tx: &Transaction) -> bool {
+        match tx {
+            Transaction::Deposit(_) => self.deposits,
+            Transaction::Withdrawal(_) => self.withdrawals,
+            _ => false,
+        }
+    }
+}
+
+impl Default for DisputePolicy {
+    fn default() -> Self {
+        DisputePolicy {
+            deposits: true,
+            withdrawals: true,
+        }
+    }
+}
+
+/// Parse a comma separated list of kinds eg "deposit,withdraw"
+impl FromStr for DisputePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut policy = DisputePolicy {
+            deposits: false,
+            withdrawals: false,
+        };
+        for kind in s.split(',').map(str::trim).filter(|kind| !kind.is_empty()) {
+            match kind {
+                "deposit" => policy.deposits = true,
+                "withdraw" => policy.withdrawals = true,
+                _ => return Err(format!("unknown disputable kind: {}", kind)),
+            }
+        }
+        Ok(policy)
+    }
+}
+
+/// Kinds of transactions still applied to a locked account, none by default
+#[derive(Debug, Clone, Copy, Default)]
+pub struct FrozenPolicy {
+    pub deposits: bool,
+    pub withdrawals: bool,
+    pub disputes: bool,
+    pub resolves: bool,
+    pub chargebacks: bool,
+}
+
+impl FrozenPolicy {
+    pub fn allows(&self, tx: &Transaction) -> bool {
+        match tx {
+            Transaction::Deposit(_) => self.deposits,
+            Transaction::Withdrawal(_) => self.withdrawals,
+            Transaction::Dispute(_) => self.disputes,
+            Transaction::Resolve(_) => self.resolves,
+            Transaction::Chargeback(_) => self.chargebacks,
+        }
+    }
+}
+
+/// Parse a comma separated list of kinds eg "dispute,resolve"
+impl FromStr for FrozenPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let mut policy = FrozenPolicy::default();
+        for kind in s.split(',').map(str::trim).filter(|kind| !kind.is_empty()) {
+            match kind {
+                "deposit" => policy.deposits = true,
+                "withdraw" => policy.withdrawals = true,
+                "dispute" => policy.disputes = true,
+                "resolve" => policy.resolves = true,
+                "chargeback" => policy.chargebacks = true,
+                _ => return Err(format!("unknown frozen kind: {}", kind)),
+            }
+        }
+        Ok(policy)
+    }
+}
+
+/// What to do with a deposit or withdrawal that reuses a tx id
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DuplicatePolicy {
+    /// Reject it with RejectReason::DuplicateTx
+    Reject,
+    /// Skip it as if it has been applied, resubmitting the same input is a no op
+    Ignore,
+}
+
+impl Default for DuplicatePolicy {
+    fn default() -> Self {
+        DuplicatePolicy::Reject
+    }
+}
+
+impl FromStr for DuplicatePolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "reject" => Ok(DuplicatePolicy::Reject),
+            "ignore" => Ok(DuplicatePolicy::Ignore),
+            _ => Err(format!("unknown duplicate policy: {}", s)),
+        }
+    }
+}
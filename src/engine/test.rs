@@ -1,38 +1,57 @@
-use super::client::Client;
+use super::client::{Balance, Client};
+use super::currency::{Amount, Currency};
+use super::error::RejectReason;
 use super::transaction::Transaction;
-use super::CreditUsd;
 use super::Usd;
 use quickcheck::{quickcheck, TestResult};
 
 use std::convert::TryFrom;
 
 const MAX: u128 = 90_000_000_000;
-lazy_static! {
-    static ref ZERO: Usd = super::zero_usd();
-    static ref C_ZERO: CreditUsd = super::zero_usd_as_credit();
+
+fn zero() -> Usd {
+    Usd::try_from(0).unwrap()
+}
+
+fn usd(amount: Amount) -> Usd {
+    match amount {
+        Amount::Usd(x) => x,
+        _ => panic!("impossible"),
+    }
+}
+
+fn usd_balance(client: &Client) -> &Balance {
+    client.balances.get(&Currency::Usd).unwrap()
+}
+
+fn total_of(client: &Client) -> Usd {
+    usd(usd_balance(client).total)
+}
+
+// Held is stored as a value >= 0, tests express it as a Debt
+fn held_as_debt(client: &Client) -> Option<Usd> {
+    usd_balance(client)
+        .held
+        .map(|held| usd(held.neg().unwrap()))
 }
 
 fn client_with_amount(total: Usd) -> Client {
     match total {
         merx::Asset::Debt(_) => panic!("impossible"),
-        merx::Asset::Credit(total) => Client::new_(false, total, None, None),
+        merx::Asset::Credit(_) => Client::new_(false, Balance::new_(Amount::Usd(total), None)),
     }
 }
 
 fn client_with_held(held: Usd) -> Client {
     let total: Usd = Usd::try_from(90).unwrap();
-    match (total, held) {
-        (merx::Asset::Credit(total), merx::Asset::Debt(held)) => {
-            Client::new_(false, total, Some(held), None)
-        }
-        (_, _) => panic!("impossible"),
-    }
+    client_with_held_and_total(held, total)
 }
 
 fn client_with_held_and_total(held: Usd, total: Usd) -> Client {
     match (total, held) {
-        (merx::Asset::Credit(total), merx::Asset::Debt(held)) => {
-            Client::new_(false, total, Some(held), None)
+        (merx::Asset::Credit(_), merx::Asset::Debt(_)) => {
+            let held = Amount::Usd(held).neg().unwrap();
+            Client::new_(false, Balance::new_(Amount::Usd(total), Some(held)))
         }
         (_, _) => panic!("impossible"),
     }
@@ -41,35 +60,56 @@ fn client_with_held_and_total(held: Usd, total: Usd) -> Client {
 fn deposit_with_amount(amount: Usd) -> Transaction {
     match amount {
         merx::Asset::Debt(_) => panic!("impossible"),
-        merx::Asset::Credit(amount) => Transaction::new_deposit(amount),
+        merx::Asset::Credit(_) => Transaction::new_deposit(Amount::Usd(amount)),
     }
 }
 
 fn withdrawl_with_amount(amount: Usd) -> Transaction {
     match amount {
         merx::Asset::Credit(_) => panic!("impossible"),
-        merx::Asset::Debt(amount) => Transaction::new_withdrawl(amount),
+        merx::Asset::Debt(_) => Transaction::new_withdrawl(Amount::Usd(amount)),
     }
 }
 
 fn dispute_with_amount(amount: Usd) -> Transaction {
     match amount {
         merx::Asset::Credit(_) => panic!("impossible"),
-        merx::Asset::Debt(amount) => Transaction::new_dispute(amount),
+        merx::Asset::Debt(_) => Transaction::new_dispute(Amount::Usd(amount)),
     }
 }
 
 fn resolve_with_amount(amount: Usd) -> Transaction {
     match amount {
         merx::Asset::Debt(_) => panic!("impossible"),
-        merx::Asset::Credit(amount) => Transaction::new_resolve(amount),
+        merx::Asset::Credit(_) => Transaction::new_resolve(Amount::Usd(amount)),
     }
 }
 
 fn chargeback_with_amount(amount: Usd) -> Transaction {
     match amount {
         merx::Asset::Credit(_) => panic!("impossible"),
-        merx::Asset::Debt(amount) => Transaction::new_chargeback(amount),
+        merx::Asset::Debt(_) => Transaction::new_chargeback(Amount::Usd(amount)),
+    }
+}
+
+fn withdrawal_dispute_with_amount(amount: Usd) -> Transaction {
+    match amount {
+        merx::Asset::Debt(_) => panic!("impossible"),
+        merx::Asset::Credit(_) => Transaction::new_dispute(Amount::Usd(amount)),
+    }
+}
+
+fn withdrawal_resolve_with_amount(amount: Usd) -> Transaction {
+    match amount {
+        merx::Asset::Credit(_) => panic!("impossible"),
+        merx::Asset::Debt(_) => Transaction::new_resolve(Amount::Usd(amount)),
+    }
+}
+
+fn withdrawal_chargeback_with_amount(amount: Usd) -> Transaction {
+    match amount {
+        merx::Asset::Debt(_) => panic!("impossible"),
+        merx::Asset::Credit(_) => Transaction::new_chargeback(Amount::Usd(amount)),
     }
 }
 
@@ -84,8 +124,8 @@ fn prop_deposit_increments_total(deposit: u128, total: u128) -> TestResult {
         (Ok(deposit), Ok(total)) => {
             let mut client = client_with_amount(total);
             let transaction = deposit_with_amount(deposit);
-            client.handle_transaction(transaction);
-            let client_total = Usd::from(merx::Asset::Credit(client.total));
+            client.handle_transaction(transaction).unwrap();
+            let client_total = total_of(&client);
             TestResult::from_bool(client_total == (deposit + total).unwrap())
         }
         _ => TestResult::discard(),
@@ -104,12 +144,16 @@ fn prop_withdrawal_decrements_total_if_possible(withdrawl: u128, total: u128) ->
         (Ok(withdrawal), Ok(total)) => {
             let mut client = client_with_amount(total);
             let transaction = withdrawl_with_amount(withdrawal);
-            client.handle_transaction(transaction);
-            let client_total = Usd::from(merx::Asset::Credit(client.total));
+            let result = client.handle_transaction(transaction);
+            let client_total = total_of(&client);
             if possible_transaction {
-                TestResult::from_bool(client_total == (total + withdrawal).unwrap())
+                TestResult::from_bool(
+                    result.is_ok() && client_total == (total + withdrawal).unwrap(),
+                )
             } else {
-                TestResult::from_bool(client_total == total)
+                TestResult::from_bool(
+                    result == Err(RejectReason::InsufficientFunds) && client_total == total,
+                )
             }
         }
         _ => TestResult::discard(),
@@ -127,14 +171,26 @@ fn prop_deposit_increments_handle(total: u128, held: u128) -> TestResult {
         (Ok(held), Ok(total)) => {
             let mut client = client_with_amount(total);
             let transaction = dispute_with_amount(held);
-            client.handle_transaction(transaction);
-            let client_held = Usd::from(merx::Asset::Debt(client.held.unwrap()));
+            client.handle_transaction(transaction).unwrap();
+            let client_held = held_as_debt(&client).unwrap();
             TestResult::from_bool(client_held == held)
         }
         _ => TestResult::discard(),
     }
 }
 
+#[test]
+fn dispute_that_makes_available_too_small_is_an_overflow() {
+    let max = Usd::try_from(14_000_000_000_000).unwrap();
+    let debt = Amount::Usd(max).neg().unwrap();
+    // Total as after a charged back dispute of a deposit already withdrawn
+    let mut client = Client::new_(false, Balance::new_(debt, None));
+    let result = client.handle_transaction(Transaction::new_dispute(debt));
+    assert_eq!(result, Err(RejectReason::Overflow));
+    assert_eq!(usd_balance(&client).held, None);
+    assert_eq!(usd_balance(&client).avaiable_amount(), debt);
+}
+
 #[quickcheck]
 fn prop_resolve_decrements_held_if_possible(held: u128, release: u128) -> TestResult {
     if (held > MAX) || (release > MAX) || held == 0 {
@@ -148,15 +204,17 @@ fn prop_resolve_decrements_held_if_possible(held: u128, release: u128) -> TestRe
         (Ok(held), Ok(release)) => {
             let mut client = client_with_held(held);
             let transaction = resolve_with_amount(release);
-            client.handle_transaction(transaction);
+            let result = client.handle_transaction(transaction);
             if possible_transaction {
-                let client_held = Usd::from(merx::Asset::Debt(client.held.unwrap()));
-                TestResult::from_bool(client_held == (held + release).unwrap())
+                let client_held = held_as_debt(&client).unwrap();
+                TestResult::from_bool(result.is_ok() && client_held == (held + release).unwrap())
             } else if same_amounts {
-                TestResult::from_bool(client.held.is_none())
+                TestResult::from_bool(result.is_ok() && held_as_debt(&client).is_none())
             } else {
-                let client_held = Usd::from(merx::Asset::Debt(client.held.unwrap()));
-                TestResult::from_bool(client_held == held)
+                let client_held = held_as_debt(&client).unwrap();
+                TestResult::from_bool(
+                    result == Err(RejectReason::InsufficientHeld) && client_held == held,
+                )
             }
         }
         _ => TestResult::discard(),
@@ -181,60 +239,54 @@ fn prop_resolve_chargeback(held_: u128, total_: u128, chargeback_: u128) -> Test
         (Ok(held), Ok(total), Ok(chargeback)) => {
             let mut client = client_with_held_and_total(held, total);
             let transaction = chargeback_with_amount(chargeback);
-            client.handle_transaction(transaction);
+            client.handle_transaction(transaction).unwrap();
             if chargeback_ > total_ && chargeback_ > held_ {
-                let condition1 = client.total == *C_ZERO;
-                let condition2 = client.held == None;
-                let condition3 = Usd::from(merx::Asset::Debt(client.overflow.unwrap()))
-                    == (total + chargeback).unwrap();
+                let condition1 = total_of(&client) == (total + chargeback).unwrap();
+                let condition2 = held_as_debt(&client) == None;
+                let condition3 = usd_balance(&client).total.is_debt();
                 TestResult::from_bool(condition1 && condition2 && condition3)
             } else if chargeback_ < total_ && chargeback_ < held_ {
-                let condition1 =
-                    Usd::from(merx::Asset::Credit(client.total)) == (total + chargeback).unwrap();
-                let condition2 = Usd::from(merx::Asset::Debt(client.held.unwrap()))
-                    == ((chargeback * -1).unwrap() + held).unwrap();
-                let condition3 = client.overflow == None;
+                let condition1 = total_of(&client) == (total + chargeback).unwrap();
+                let condition2 =
+                    held_as_debt(&client).unwrap() == ((chargeback * -1).unwrap() + held).unwrap();
+                let condition3 = !usd_balance(&client).total.is_debt();
                 TestResult::from_bool(condition1 && condition2 && condition3)
             } else if chargeback_ == total_ && chargeback_ == held_ {
-                let condition1 = client.total == *C_ZERO;
-                let condition2 = client.held == None;
-                let condition3 = client.overflow == None;
+                let condition1 = total_of(&client) == zero();
+                let condition2 = held_as_debt(&client) == None;
+                let condition3 = !usd_balance(&client).total.is_debt();
                 TestResult::from_bool(condition1 && condition2 && condition3)
             } else if chargeback_ == total_ && chargeback_ > held_ {
-                let condition1 = client.total == *C_ZERO;
-                let condition2 = client.held == None;
-                let condition3 = client.overflow == None;
+                let condition1 = total_of(&client) == zero();
+                let condition2 = held_as_debt(&client) == None;
+                let condition3 = !usd_balance(&client).total.is_debt();
                 TestResult::from_bool(condition1 && condition2 && condition3)
             } else if chargeback_ == total_ && chargeback_ < held_ {
-                let condition1 = client.total == *C_ZERO;
-                let condition2 = Usd::from(merx::Asset::Debt(client.held.unwrap()))
-                    == ((chargeback * -1).unwrap() + held).unwrap();
-                let condition3 = client.overflow == None;
+                let condition1 = total_of(&client) == zero();
+                let condition2 =
+                    held_as_debt(&client).unwrap() == ((chargeback * -1).unwrap() + held).unwrap();
+                let condition3 = !usd_balance(&client).total.is_debt();
                 TestResult::from_bool(condition1 && condition2 && condition3)
             } else if chargeback_ > total_ && chargeback_ == held_ {
-                let condition1 = client.total == *C_ZERO;
-                let condition2 = client.held == None;
-                let condition3 = Usd::from(merx::Asset::Debt(client.overflow.unwrap()))
-                    == (total + chargeback).unwrap();
+                let condition1 = total_of(&client) == (total + chargeback).unwrap();
+                let condition2 = held_as_debt(&client) == None;
+                let condition3 = usd_balance(&client).total.is_debt();
                 TestResult::from_bool(condition1 && condition2 && condition3)
             } else if chargeback_ < total_ && chargeback_ == held_ {
-                let condition1 =
-                    Usd::from(merx::Asset::Credit(client.total)) == (total + chargeback).unwrap();
-                let condition2 = client.held == None;
-                let condition3 = client.overflow == None;
+                let condition1 = total_of(&client) == (total + chargeback).unwrap();
+                let condition2 = held_as_debt(&client) == None;
+                let condition3 = !usd_balance(&client).total.is_debt();
                 TestResult::from_bool(condition1 && condition2 && condition3)
             } else if chargeback_ < total_ && chargeback_ > held_ {
-                let condition1 =
-                    Usd::from(merx::Asset::Credit(client.total)) == (total + chargeback).unwrap();
-                let condition2 = client.held == None;
-                let condition3 = client.overflow == None;
+                let condition1 = total_of(&client) == (total + chargeback).unwrap();
+                let condition2 = held_as_debt(&client) == None;
+                let condition3 = !usd_balance(&client).total.is_debt();
                 TestResult::from_bool(condition1 && condition2 && condition3)
             } else if chargeback_ > total_ && chargeback_ < held_ {
-                let condition1 = client.total == *C_ZERO;
-                let condition2 = Usd::from(merx::Asset::Debt(client.held.unwrap()))
-                    == ((chargeback * -1).unwrap() + held).unwrap();
-                let condition3 = Usd::from(merx::Asset::Debt(client.overflow.unwrap()))
-                    == (total + chargeback).unwrap();
+                let condition1 = total_of(&client) == (total + chargeback).unwrap();
+                let condition2 =
+                    held_as_debt(&client).unwrap() == ((chargeback * -1).unwrap() + held).unwrap();
+                let condition3 = usd_balance(&client).total.is_debt();
                 TestResult::from_bool(condition1 && condition2 && condition3)
             } else {
                 panic!("forgot case")
@@ -243,3 +295,124 @@ fn prop_resolve_chargeback(held_: u128, total_: u128, chargeback_: u128) -> Test
         _ => TestResult::discard(),
     }
 }
+
+#[quickcheck]
+fn prop_withdrawal_dispute_held_withdrawn_amount(total: u128, withdrawal: u128) -> TestResult {
+    if (total > MAX) || (withdrawal > MAX) || withdrawal == 0 || withdrawal > total {
+        return TestResult::discard();
+    }
+    let debt = Usd::try_from(withdrawal as i128 * -1);
+    let credit = Usd::try_from(withdrawal as i128);
+    let total = Usd::try_from(total as i128);
+    match (debt, credit, total) {
+        (Ok(debt), Ok(credit), Ok(total)) => {
+            let mut client = client_with_amount(total);
+            client
+                .handle_transaction(withdrawl_with_amount(debt))
+                .unwrap();
+            client
+                .handle_transaction(withdrawal_dispute_with_amount(credit))
+                .unwrap();
+            let condition1 = total_of(&client) == total;
+            let condition2 = held_as_debt(&client).unwrap() == debt;
+            let condition3 = usd(usd_balance(&client).avaiable_amount()) == (total + debt).unwrap();
+            TestResult::from_bool(condition1 && condition2 && condition3)
+        }
+        _ => TestResult::discard(),
+    }
+}
+
+#[quickcheck]
+fn prop_withdrawal_resolve_confirm_withdrawal(total: u128, withdrawal: u128) -> TestResult {
+    if (total > MAX) || (withdrawal > MAX) || withdrawal == 0 || withdrawal > total {
+        return TestResult::discard();
+    }
+    let debt = Usd::try_from(withdrawal as i128 * -1);
+    let credit = Usd::try_from(withdrawal as i128);
+    let total = Usd::try_from(total as i128);
+    match (debt, credit, total) {
+        (Ok(debt), Ok(credit), Ok(total)) => {
+            let mut client = client_with_amount(total);
+            client
+                .handle_transaction(withdrawl_with_amount(debt))
+                .unwrap();
+            client
+                .handle_transaction(withdrawal_dispute_with_amount(credit))
+                .unwrap();
+            client
+                .handle_transaction(withdrawal_resolve_with_amount(debt))
+                .unwrap();
+            let condition1 = total_of(&client) == (total + debt).unwrap();
+            let condition2 = held_as_debt(&client) == None;
+            let condition3 = !client.locked;
+            TestResult::from_bool(condition1 && condition2 && condition3)
+        }
+        _ => TestResult::discard(),
+    }
+}
+
+#[quickcheck]
+fn prop_withdrawal_chargeback_credit_client(total: u128, withdrawal: u128) -> TestResult {
+    if (total > MAX) || (withdrawal > MAX) || withdrawal == 0 || withdrawal > total {
+        return TestResult::discard();
+    }
+    let debt = Usd::try_from(withdrawal as i128 * -1);
+    let credit = Usd::try_from(withdrawal as i128);
+    let total = Usd::try_from(total as i128);
+    match (debt, credit, total) {
+        (Ok(debt), Ok(credit), Ok(total)) => {
+            let mut client = client_with_amount(total);
+            client
+                .handle_transaction(withdrawl_with_amount(debt))
+                .unwrap();
+            client
+                .handle_transaction(withdrawal_dispute_with_amount(credit))
+                .unwrap();
+            client
+                .handle_transaction(withdrawal_chargeback_with_amount(credit))
+                .unwrap();
+            let condition1 = total_of(&client) == total;
+            let condition2 = held_as_debt(&client) == None;
+            let condition3 = client.locked;
+            TestResult::from_bool(condition1 && condition2 && condition3)
+        }
+        _ => TestResult::discard(),
+    }
+}
+
+#[test]
+fn currencies_have_separate_balances() {
+    let usd = Amount::parse(Currency::Usd, "10.5").unwrap();
+    let eur = Amount::parse(Currency::Eur, "3").unwrap();
+    let mut client = Client::new();
+    client
+        .handle_transaction(Transaction::new_deposit(usd))
+        .unwrap();
+    client
+        .handle_transaction(Transaction::new_deposit(eur))
+        .unwrap();
+    client
+        .handle_transaction(Transaction::new_withdrawl(eur.neg().unwrap()))
+        .unwrap();
+    assert_eq!(client.balances.get(&Currency::Usd).unwrap().total, usd);
+    assert!(client.balances.get(&Currency::Eur).unwrap().total.is_zero());
+    assert!(client.balances.get(&Currency::Gbp).is_none());
+}
+
+#[test]
+fn amounts_of_different_currencies_do_not_mix() {
+    let usd = Amount::parse(Currency::Usd, "1").unwrap();
+    let gbp = Amount::parse(Currency::Gbp, "1").unwrap();
+    assert_eq!(usd + gbp, None);
+    assert_eq!(usd - gbp, None);
+}
+
+#[test]
+fn overflow_of_old_balances_becomes_a_negative_total() {
+    let json = r#"{"total":{"currency":"USD","value":"2"},"held":null,
+        "overflow":{"currency":"USD","value":"-5"}}"#;
+    let balance: Balance = serde_json::from_str(json).unwrap();
+    assert_eq!(balance.total, Amount::parse(Currency::Usd, "-3").unwrap());
+    let json = serde_json::to_string(&balance).unwrap();
+    assert!(!json.contains("overflow"));
+}
@@ -1,44 +1,48 @@
-use super::CreditUsd;
-use super::DebtUsd;
-use super::Usd;
+use super::currency::Amount;
+use serde::{Deserialize, Serialize};
 
-#[derive(Debug, Clone)]
+/// Deposit amounts are always Credit and Withdrawal amounts are always Debt.
+/// Dispute, Resolve and Chargeback carry the amount of the linked tx with the sign they have
+/// on the client total: a Debt when the linked tx is a deposit and a Credit when it is a
+/// withdrawal (for the resolve the signs are inverted)
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Transaction {
-    Deposit(CreditUsd),
-    Withdrawal(DebtUsd),
-    Dispute(DebtUsd),
-    Resolve(CreditUsd),
-    Chargeback(DebtUsd),
+    Deposit(Amount),
+    Withdrawal(Amount),
+    Dispute(Amount),
+    Resolve(Amount),
+    Chargeback(Amount),
 }
 
 impl Transaction {
-    pub fn new_deposit(amount: CreditUsd) -> Self {
+    pub fn new_deposit(amount: Amount) -> Self {
         Self::Deposit(amount)
     }
 
-    pub fn new_withdrawl(amount: DebtUsd) -> Self {
+    pub fn new_withdrawl(amount: Amount) -> Self {
         Self::Withdrawal(amount)
     }
 
-    pub fn new_dispute(amount: DebtUsd) -> Self {
+    pub fn new_dispute(amount: Amount) -> Self {
         Self::Dispute(amount)
     }
 
-    pub fn new_resolve(amount: CreditUsd) -> Self {
+    pub fn new_resolve(amount: Amount) -> Self {
         Self::Resolve(amount)
     }
 
-    pub fn new_chargeback(amount: DebtUsd) -> Self {
+    pub fn new_chargeback(amount: Amount) -> Self {
         Self::Chargeback(amount)
     }
 
-    pub fn get_amount(&self) -> Usd {
+    pub fn get_amount(&self) -> Amount {
         match &self {
-            Self::Deposit(x) => Usd::from(merx::Asset::Credit(x.clone())),
-            Self::Withdrawal(x) => Usd::from(merx::Asset::Debt(x.clone())),
-            Self::Dispute(x) => Usd::from(merx::Asset::Debt(x.clone())),
-            Self::Resolve(x) => Usd::from(merx::Asset::Credit(x.clone())),
-            Self::Chargeback(x) => Usd::from(merx::Asset::Debt(x.clone())),
+            Self::Deposit(x) => *x,
+            Self::Withdrawal(x) => *x,
+            Self::Dispute(x) => *x,
+            Self::Resolve(x) => *x,
+            Self::Chargeback(x) => *x,
         }
     }
 }
@@ -0,0 +1,15 @@
+#![feature(const_panic)]
+#![feature(min_const_generics)]
+
+#[cfg(test)]
+#[macro_use(quickcheck)]
+extern crate quickcheck_macros;
+
+mod api;
+pub mod db;
+pub mod engine;
+pub mod output;
+pub mod parser;
+pub mod server;
+
+pub use api::{Engine, Operation, Outcome, Step};
@@ -1,35 +1,333 @@
-#![feature(const_panic)]
-#![feature(min_const_generics)]
+use pinoedino::db::{Db, DurableDb, Limits, Snapshot, Store};
+use pinoedino::{engine, output, parser, server, Engine};
+use std::sync::{Arc, Mutex};
 
-#[cfg(test)]
-#[macro_use(quickcheck)]
-extern crate quickcheck_macros;
+fn main() {
+    let mut path: Option<String> = None;
+    let mut policy = engine::DisputePolicy::default();
+    let mut limits = Limits::default();
+    let mut store_dir: Option<String> = None;
+    let mut checkpoint: u64 = 64;
+    let mut duplicates = engine::DuplicatePolicy::default();
+    let mut frozen = engine::FrozenPolicy::default();
+    let mut load_state: Option<String> = None;
+    let mut save_state: Option<String> = None;
+    let mut input_format: Option<parser::InputFormat> = None;
+    let mut rejects: Option<String> = None;
+    let mut decimals: u32 = 4;
+    let mut sort = output::Sort::default();
+    let mut format = output::Format::Csv;
+    let mut clients: Option<std::collections::HashSet<u16>> = None;
+    let mut workers: usize = 1;
+    let mut serve = false;
+    let mut listen = String::from("127.0.0.1:7878");
+    let mut http: Option<String> = None;
+    let mut statement = false;
+    let mut client: Option<u16> = None;
 
-#[macro_use]
-extern crate lazy_static;
+    let mut args = std::env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match &arg[..] {
+            "--disputable" => {
+                policy = args
+                    .next()
+                    .expect("no disputable kinds")
+                    .parse()
+                    .expect("invalid disputable kinds")
+            }
+            "--input-format" => {
+                input_format = Some(
+                    args.next()
+                        .expect("no input format")
+                        .parse()
+                        .expect("invalid input format"),
+                )
+            }
+            "--duplicates" => {
+                duplicates = args
+                    .next()
+                    .expect("no duplicate policy")
+                    .parse()
+                    .expect("invalid duplicate policy")
+            }
+            "--frozen" => {
+                frozen = args
+                    .next()
+                    .expect("no frozen kinds")
+                    .parse()
+                    .expect("invalid frozen kinds")
+            }
+            "--retention" => {
+                limits.retention = Some(
+                    args.next()
+                        .expect("no retention")
+                        .parse()
+                        .expect("invalid retention"),
+                )
+            }
+            "--max-memory" => {
+                let mib: usize = args
+                    .next()
+                    .expect("no max memory")
+                    .parse()
+                    .expect("invalid max memory");
+                limits.max_memory = Some(mib * 1024 * 1024)
+            }
+            "--store" => store_dir = Some(args.next().expect("no store dir")),
+            "--load-state" => load_state = Some(args.next().expect("no load state path")),
+            "--save-state" => save_state = Some(args.next().expect("no save state path")),
+            "--checkpoint" => {
+                checkpoint = args
+                    .next()
+                    .expect("no checkpoint")
+                    .parse()
+                    .expect("invalid checkpoint")
+            }
+            "--decimals" => {
+                decimals = args
+                    .next()
+                    .expect("no decimals")
+                    .parse()
+                    .ok()
+                    .filter(|decimals| *decimals <= output::MAX_DECIMALS)
+                    .expect("invalid decimals")
+            }
+            "--format" => {
+                format = args
+                    .next()
+                    .expect("no format")
+                    .parse()
+                    .expect("invalid format")
+            }
+            "--sort" => {
+                sort = args
+                    .next()
+                    .expect("no sort key")
+                    .parse()
+                    .expect("invalid sort key")
+            }
+            "--clients" => {
+                clients = Some(
+                    args.next()
+                        .expect("no clients")
+                        .split(',')
+                        .map(|id| id.trim().parse().expect("invalid client id"))
+                        .collect(),
+                )
+            }
+            "--workers" => {
+                workers = args
+                    .next()
+                    .expect("no workers")
+                    .parse()
+                    .expect("invalid workers")
+            }
+            "--rejects" => rejects = Some(args.next().expect("no rejects path")),
+            "--listen" => listen = args.next().expect("no listen address"),
+            "--http" => http = Some(args.next().expect("no http address")),
+            "--client" => {
+                client = Some(
+                    args.next()
+                        .expect("no client")
+                        .parse()
+                        .expect("invalid client id"),
+                )
+            }
+            "serve" if path.is_none() && !serve && !statement => serve = true,
+            "statement" if path.is_none() && !serve && !statement => statement = true,
+            _ => path = Some(arg),
+        }
+    }
+    let statement = if statement {
+        Some(client.expect("statement needs --client"))
+    } else {
+        None
+    };
+    assert!(
+        !serve || rejects.is_none(),
+        "--rejects can not be used with serve, the rejected rows are acked"
+    );
+    let mut steps = Vec::new();
+    let mut rejects = rejects.map(|path| {
+        let file = std::fs::File::create(path).expect("invalid rejects path");
+        output::RejectWriter::new(file).expect("can not write rejects")
+    });
+    let mut report = |rejection: parser::Rejection| match &mut rejects {
+        Some(rejects) => rejects.write(&rejection).expect("can not write rejects"),
+        None => eprintln!(
+            "WARNING: ignored row [{}] {}",
+            rejection.reason.code(),
+            rejection
+        ),
+    };
 
-mod db;
-mod engine;
-mod output;
-mod parser;
-fn main() {
-    let path: String = std::env::args()
-        .collect::<Vec<String>>()
-        .get(1)
-        .expect("no path")
-        .parse()
-        .expect("invalid path");
+    let mut rows = if serve {
+        assert!(statement.is_none(), "statement can not be served");
+        let store = open_store(store_dir, load_state, limits, checkpoint);
+        let mut engine = Engine::with_store(store, policy);
+        engine.set_duplicates(duplicates);
+        engine.set_frozen(frozen);
+        let engine = Arc::new(Mutex::new(engine));
 
-    let mut db = db::Db::new();
+        let runtime = tokio::runtime::Runtime::new().expect("can not start the runtime");
+        runtime
+            .block_on(async {
+                let (stop, stopped) = tokio::sync::watch::channel(false);
+                tokio::spawn(async move {
+                    tokio::signal::ctrl_c().await.expect("can not wait ctrl-c");
+                    let _ = stop.send(true);
+                });
+                let shutdown = || {
+                    let mut stopped = stopped.clone();
+                    async move {
+                        let _ = stopped.changed().await;
+                    }
+                };
 
-    parser::pre_parse(path.clone(), &mut db);
+                let listener = tokio::net::TcpListener::bind(&listen)
+                    .await
+                    .expect("can not listen");
+                let rows = server::serve(listener, engine.clone(), input_format, shutdown());
+                let api = async {
+                    match &http {
+                        None => Ok(()),
+                        Some(http) => {
+                            let listener = tokio::net::TcpListener::bind(http)
+                                .await
+                                .expect("can not listen http");
+                            server::serve_http(listener, engine.clone(), decimals, shutdown()).await
+                        }
+                    }
+                };
+                let (rows, api) = tokio::join!(rows, api);
+                rows.and(api)
+            })
+            .expect("server error");
+        // Stop the connections still open
+        drop(runtime);
 
-    parser::parse(path, &mut db);
+        let engine = engine.lock().expect("engine poisoned");
+        save(&engine, save_state);
+        output_rows(&engine, &clients)
+    } else if workers > 1 {
+        assert!(
+            statement.is_none(),
+            "--workers can not be used with statement"
+        );
+        assert!(
+            store_dir.is_none() && load_state.is_none() && save_state.is_none(),
+            "--workers can not be used with --store, --load-state or --save-state"
+        );
+        assert!(
+            limits.retention.is_none() && limits.max_memory.is_none(),
+            "--workers can not be used with --retention or --max-memory"
+        );
+        let engines = (0..workers)
+            .map(|_| {
+                let mut engine = Engine::new(policy, limits);
+                engine.set_duplicates(duplicates);
+                engine.set_frozen(frozen);
+                engine
+            })
+            .collect();
+        let (input, input_format) = open_input(path, input_format);
+        parser::parse_sharded(input, input_format, engines, &mut report)
+            .expect("can not read the input")
+            .iter()
+            .flat_map(|engine| output_rows(engine, &clients))
+            .collect()
+    } else {
+        let store = open_store(store_dir, load_state, limits, checkpoint);
+        let mut engine = Engine::with_store(store, policy);
+        engine.set_duplicates(duplicates);
+        engine.set_frozen(frozen);
+        if statement.is_some() {
+            engine.keep_history(statement);
+        }
 
-    output::OutputRow::print_header();
+        let (input, input_format) = open_input(path, input_format);
+        parser::parse(input, input_format, &mut engine, &mut report)
+            .expect("can not read the input or write the store");
+        if let Some(client) = statement {
+            steps = engine.history(client).to_vec();
+        }
 
-    for (key, value) in db.get_clients() {
-        let output_row: output::OutputRow = value.into();
-        output_row.print(key);
+        save(&engine, save_state);
+        output_rows(&engine, &clients)
+    };
+
+    if let Some(rejects) = &mut rejects {
+        rejects.flush().expect("can not write rejects");
     }
+
+    output::sort(&mut rows, sort);
+
+    let stdout = std::io::stdout();
+    let writer = std::io::BufWriter::new(stdout.lock());
+    match statement {
+        Some(client) => output::write_statement(writer, client, &steps, format, decimals),
+        None => output::write(writer, &rows, format, decimals),
+    }
+    .expect("can not write output");
+}
+
+// No path or "-" reads from stdin, the format is detected from the path if not given
+fn open_input(
+    path: Option<String>,
+    format: Option<parser::InputFormat>,
+) -> (Box<dyn std::io::Read>, parser::InputFormat) {
+    let path = path.filter(|path| path != "-");
+    let format = format.unwrap_or_else(|| match &path {
+        Some(path) => parser::InputFormat::from_path(path),
+        None => parser::InputFormat::Csv,
+    });
+    let input: Box<dyn std::io::Read> = match path {
+        Some(path) => Box::new(std::fs::File::open(path).expect("invalid file")),
+        None => Box::new(std::io::stdin()),
+    };
+    (input, format)
+}
+
+fn open_store(
+    store_dir: Option<String>,
+    load_state: Option<String>,
+    limits: Limits,
+    checkpoint: u64,
+) -> Box<dyn Store + Send> {
+    match (store_dir, load_state) {
+        (Some(_), Some(_)) => panic!("--load-state can not be used with --store"),
+        (Some(dir), None) => Box::new(
+            DurableDb::open(dir, limits, checkpoint.saturating_mul(1 << 20))
+                .expect("can not open the store"),
+        ),
+        (None, Some(path)) => {
+            let file = std::fs::File::open(path).expect("invalid load state path");
+            let snapshot = Snapshot::load(file).expect("invalid state");
+            Box::new(Db::from_snapshot(snapshot, limits).expect("invalid state"))
+        }
+        (None, None) => Box::new(Db::new(limits)),
+    }
+}
+
+fn save<S: Store>(engine: &Engine<S>, save_state: Option<String>) {
+    if let Some(path) = save_state {
+        let file = std::fs::File::create(path).expect("invalid save state path");
+        engine.snapshot().save(file).expect("can not write state");
+    }
+}
+
+// Rows of the clients of engine that are in clients, all if clients is None
+fn output_rows<S: Store>(
+    engine: &Engine<S>,
+    clients: &Option<std::collections::HashSet<u16>>,
+) -> Vec<output::OutputRow> {
+    engine
+        .clients()
+        .filter(|(id, _)| {
+            clients
+                .as_ref()
+                .map_or(true, |clients| clients.contains(id))
+        })
+        .flat_map(|(id, client)| output::OutputRow::from_client(id, client))
+        .collect()
 }
@@ -0,0 +1,350 @@
+use crate::api::Step;
+use crate::engine::{Amount, Balance, Client, Currency};
+use crate::parser::Rejection;
+use crate::Operation;
+use serde::Serialize;
+use std::convert::{From, Into};
+use std::io::Write;
+use std::str::FromStr;
+
+#[cfg(test)]
+mod test;
+
+/// Max decimals accepted by Fixed::format, every amount fits in an i128 with this precision
+pub const MAX_DECIMALS: u32 = 20;
+
+/// Signed fixed point number equal to value * 10^-precision
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Fixed {
+    value: i128,
+    precision: u32,
+}
+
+impl Fixed {
+    /// Format with exactly `decimals` fractional digits, extra digits are rounded half away
+    /// from zero. Panics if decimals > MAX_DECIMALS
+    pub fn format(&self, decimals: u32) -> String {
+        assert!(decimals <= MAX_DECIMALS, "too many decimals: {}", decimals);
+        let mut abs = self.value.abs();
+        if decimals < self.precision {
+            let divisor = 10_i128.pow(self.precision - decimals);
+            abs = (abs + divisor / 2) / divisor;
+        } else {
+            abs *= 10_i128.pow(decimals - self.precision);
+        }
+        let sign = if self.value < 0 && abs != 0 { "-" } else { "" };
+        let scale = 10_i128.pow(decimals);
+        if decimals == 0 {
+            format!("{}{}", sign, abs)
+        } else {
+            format!(
+                "{}{}.{:0width$}",
+                sign,
+                abs / scale,
+                abs % scale,
+                width = decimals as usize
+            )
+        }
+    }
+}
+
+impl From<Amount> for Fixed {
+    fn from(amount: Amount) -> Fixed {
+        // Integer and fractional parts have the same sign
+        let (integer, fraction, _) = amount.to_parts();
+        let precision = amount.currency().decimals();
+        Fixed {
+            value: integer * 10_i128.pow(precision) + fraction,
+            precision,
+        }
+    }
+}
+
+pub struct OutputRow {
+    client: u16,
+    currency: Currency,
+    available: Fixed,
+    held: Fixed,
+    total: Fixed,
+    locked: bool,
+}
+
+/// Serializable version of OutputRow, amounts are decimal strings to not lose precision
+#[derive(Debug, Serialize)]
+pub struct OutputRecord {
+    client: u16,
+    currency: &'static str,
+    available: String,
+    held: String,
+    total: String,
+    locked: bool,
+}
+
+impl OutputRow {
+    pub fn to_record(&self, decimals: u32) -> OutputRecord {
+        OutputRecord {
+            client: self.client,
+            currency: self.currency.code(),
+            available: self.available.format(decimals),
+            held: self.held.format(decimals),
+            total: self.total.format(decimals),
+            locked: self.locked,
+        }
+    }
+
+    /// A row for every currency held by client, a client without balances, eg frozen before
+    /// its first deposit, has a row of zeros in the default currency
+    pub fn from_client(id: u16, client: &Client) -> Vec<OutputRow> {
+        if client.balances.is_empty() {
+            let balance = Balance::new(Currency::default());
+            return vec![OutputRow::from_balance(id, &balance, client.locked)];
+        }
+        client
+            .balances
+            .values()
+            .map(|balance| OutputRow::from_balance(id, balance, client.locked))
+            .collect()
+    }
+
+    fn from_balance(id: u16, balance: &Balance, locked: bool) -> Self {
+        let available: Fixed = balance.avaiable_amount().into();
+        let held: Fixed = match balance.held {
+            None => Amount::zero(balance.total.currency()).into(),
+            Some(held) => held.into(),
+        };
+        let total: Fixed = balance.total.into();
+        OutputRow {
+            client: id,
+            currency: balance.total.currency(),
+            available,
+            held,
+            total,
+            locked,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    Csv,
+    /// A single json array
+    Json,
+    /// A json object per line
+    Jsonl,
+}
+
+impl FromStr for Format {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "csv" => Ok(Format::Csv),
+            "json" => Ok(Format::Json),
+            "jsonl" => Ok(Format::Jsonl),
+            _ => Err(format!("unknown format: {}", s)),
+        }
+    }
+}
+
+pub fn write<W: Write>(
+    writer: W,
+    rows: &[OutputRow],
+    format: Format,
+    decimals: u32,
+) -> std::io::Result<()> {
+    let records = rows.iter().map(|row| row.to_record(decimals));
+    write_records(writer, records, format)
+}
+
+/// A step of a client history with the balance of the currency of the step after it
+#[derive(Debug, Serialize)]
+pub struct StatementRecord {
+    step: usize,
+    #[serde(rename = "type")]
+    type_: &'static str,
+    tx: u32,
+    amount: Option<String>,
+    status: &'static str,
+    reason: Option<&'static str>,
+    currency: Option<&'static str>,
+    available: Option<String>,
+    held: Option<String>,
+    total: Option<String>,
+    locked: bool,
+    /// Audit reason of admin rows
+    note: Option<String>,
+}
+
+impl StatementRecord {
+    /// step is the position of the step in the history starting from 1
+    pub fn from_step(step: usize, client: u16, history: &Step, decimals: u32) -> Self {
+        let operation = history.operation.as_ref();
+        let amount = operation.and_then(|operation| operation.amount());
+        let balance = history
+            .balance
+            .as_ref()
+            .map(|balance| OutputRow::from_balance(client, balance, history.locked));
+        StatementRecord {
+            step,
+            type_: operation.map_or("", |operation| operation.kind()),
+            tx: history.tx,
+            amount: amount.map(|amount| Fixed::from(amount).format(decimals)),
+            status: if history.outcome.is_ok() {
+                "applied"
+            } else {
+                "rejected"
+            },
+            reason: history.outcome.as_ref().err().map(|reason| reason.code()),
+            currency: balance.as_ref().map(|row| row.currency.code()),
+            available: balance.as_ref().map(|row| row.available.format(decimals)),
+            held: balance.as_ref().map(|row| row.held.format(decimals)),
+            total: balance.as_ref().map(|row| row.total.format(decimals)),
+            locked: history.locked,
+            note: operation.and_then(|operation| match operation {
+                Operation::Transfer { to_client, .. } if *to_client != client => {
+                    Some(format!("to client {}", to_client))
+                }
+                Operation::Transfer {
+                    client: from_client,
+                    ..
+                } if *from_client != client => Some(format!("from client {}", from_client)),
+                _ => operation.reason().map(String::from),
+            }),
+        }
+    }
+}
+
+/// Write the history of client, see StatementRecord
+pub fn write_statement<W: Write>(
+    writer: W,
+    client: u16,
+    steps: &[Step],
+    format: Format,
+    decimals: u32,
+) -> std::io::Result<()> {
+    let records = steps
+        .iter()
+        .enumerate()
+        .map(|(i, step)| StatementRecord::from_step(i + 1, client, step, decimals));
+    write_records(writer, records, format)
+}
+
+fn write_records<W: Write, T: Serialize, I: Iterator<Item = T>>(
+    mut writer: W,
+    records: I,
+    format: Format,
+) -> std::io::Result<()> {
+    match format {
+        Format::Csv => {
+            let mut wtr = csv::Writer::from_writer(writer);
+            for record in records {
+                wtr.serialize(record)?;
+            }
+            wtr.flush()
+        }
+        Format::Json => {
+            serde_json::to_writer(&mut writer, &records.collect::<Vec<T>>())?;
+            writeln!(writer)?;
+            writer.flush()
+        }
+        Format::Jsonl => {
+            for record in records {
+                serde_json::to_writer(&mut writer, &record)?;
+                writeln!(writer)?;
+            }
+            writer.flush()
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SortKey {
+    Client,
+    Available,
+    Held,
+    Total,
+    Locked,
+}
+
+/// Order of the output rows, ties are always broken by client id and currency
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Sort {
+    pub key: SortKey,
+    pub descending: bool,
+}
+
+impl Default for Sort {
+    fn default() -> Self {
+        Sort {
+            key: SortKey::Client,
+            descending: false,
+        }
+    }
+}
+
+/// Parse a key name, a leading '-' means descending order eg "-total"
+impl FromStr for Sort {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        let (descending, key) = match s.strip_prefix('-') {
+            Some(key) => (true, key),
+            None => (false, s),
+        };
+        let key = match key {
+            "client" => SortKey::Client,
+            "available" => SortKey::Available,
+            "held" => SortKey::Held,
+            "total" => SortKey::Total,
+            "locked" => SortKey::Locked,
+            _ => return Err(format!("unknown sort key: {}", key)),
+        };
+        Ok(Sort { key, descending })
+    }
+}
+
+pub fn sort(rows: &mut [OutputRow], sort: Sort) {
+    rows.sort_by(|a, b| {
+        let ordering = match sort.key {
+            SortKey::Client => a.client.cmp(&b.client),
+            SortKey::Available => a.available.cmp(&b.available),
+            SortKey::Held => a.held.cmp(&b.held),
+            SortKey::Total => a.total.cmp(&b.total),
+            SortKey::Locked => a.locked.cmp(&b.locked),
+        };
+        let ordering = if sort.descending {
+            ordering.reverse()
+        } else {
+            ordering
+        };
+        ordering
+            .then(a.client.cmp(&b.client))
+            .then(a.currency.cmp(&b.currency))
+    });
+}
+
+/// Write every rejected row as csv: line,reason,message,raw...
+/// raw is the original row with a column for every field, a jsonl row is a single column
+pub struct RejectWriter<W: Write> {
+    wtr: csv::Writer<W>,
+}
+
+impl<W: Write> RejectWriter<W> {
+    pub fn new(writer: W) -> csv::Result<Self> {
+        let mut wtr = csv::WriterBuilder::new().flexible(true).from_writer(writer);
+        wtr.write_record(&["line", "reason", "message", "raw"])?;
+        Ok(RejectWriter { wtr })
+    }
+
+    pub fn write(&mut self, rejection: &Rejection) -> csv::Result<()> {
+        let line = rejection.line.to_string();
+        let message = rejection.reason.to_string();
+        let fields = [&line[..], rejection.reason.code(), &message[..]];
+        let raw = rejection.raw.iter().map(|field| &field[..]);
+        self.wtr.write_record(fields.iter().copied().chain(raw))
+    }
+
+    pub fn flush(&mut self) -> std::io::Result<()> {
+        self.wtr.flush()
+    }
+}
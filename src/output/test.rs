@@ -0,0 +1,193 @@
+use super::Fixed;
+use crate::engine::{Amount, Currency};
+use quickcheck::{quickcheck, TestResult};
+
+const MAX: u64 = 90_000_000_000;
+
+fn usd(amount: &str) -> Amount {
+    Amount::parse(Currency::Usd, amount).unwrap()
+}
+
+fn fixed(amount: &str) -> Fixed {
+    usd(amount).into()
+}
+
+#[test]
+fn format_pad_fractional_part() {
+    assert_eq!(fixed("0.05").format(4), "0.0500");
+    assert_eq!(fixed("10.87").format(4), "10.8700");
+    assert_eq!(fixed("0").format(4), "0.0000");
+    assert_eq!(fixed("3.0001").format(4), "3.0001");
+}
+
+#[test]
+fn format_keep_sign() {
+    assert_eq!(fixed("-0.05").format(4), "-0.0500");
+    assert_eq!(fixed("-12.5").format(4), "-12.5000");
+}
+
+#[test]
+fn format_with_custom_decimals() {
+    assert_eq!(fixed("1.2345").format(2), "1.23");
+    assert_eq!(fixed("1.2350").format(2), "1.24");
+    assert_eq!(fixed("-1.2350").format(2), "-1.24");
+    assert_eq!(fixed("-0.0040").format(2), "0.00");
+    assert_eq!(fixed("7.5").format(0), "8");
+    assert_eq!(fixed("7.5").format(6), "7.500000");
+}
+
+#[test]
+fn format_the_max_amount_with_max_decimals() {
+    assert_eq!(
+        fixed("-14000000000000").format(super::MAX_DECIMALS),
+        "-14000000000000.00000000000000000000"
+    );
+}
+
+#[quickcheck]
+fn prop_format_round_trip(integer: u64, fraction: u16, negative: bool) -> TestResult {
+    if integer > MAX || fraction > 9_999 || (integer == 0 && fraction == 0 && negative) {
+        return TestResult::discard();
+    }
+    let sign = if negative { "-" } else { "" };
+    let input = format!("{}{}.{:04}", sign, integer, fraction);
+    let amount = Amount::parse(Currency::Usd, &input).unwrap();
+    let output = Fixed::from(amount).format(4);
+    TestResult::from_bool(output == input && Amount::parse(Currency::Usd, &output) == Some(amount))
+}
+
+fn rows() -> Vec<super::OutputRow> {
+    let mut rows = vec![];
+    for (id, amount) in &[(3, "5"), (1, "7"), (2, "1")] {
+        let mut client = crate::engine::Client::new();
+        let deposit = crate::engine::Transaction::new_deposit(usd(amount));
+        client.handle_transaction(deposit).unwrap();
+        rows.extend(super::OutputRow::from_client(*id, &client));
+    }
+    rows
+}
+
+fn clients(rows: &[super::OutputRow]) -> Vec<u16> {
+    rows.iter().map(|row| row.client).collect()
+}
+
+#[test]
+fn sort_by_client_by_default() {
+    let mut rows = rows();
+    super::sort(&mut rows, super::Sort::default());
+    assert_eq!(clients(&rows), vec![1, 2, 3]);
+}
+
+#[test]
+fn sort_by_total_descending() {
+    let mut rows = rows();
+    super::sort(&mut rows, "-total".parse().unwrap());
+    assert_eq!(clients(&rows), vec![1, 3, 2]);
+    super::sort(&mut rows, "total".parse().unwrap());
+    assert_eq!(clients(&rows), vec![2, 3, 1]);
+}
+
+#[test]
+fn clients_without_balances_have_a_row() {
+    let mut client = crate::engine::Client::new();
+    client.freeze("kyc".to_string()).unwrap();
+    let rows = super::OutputRow::from_client(4, &client);
+    assert_eq!(rows.len(), 1);
+    assert!(rows[0].locked);
+    assert_eq!(rows[0].currency, Currency::Usd);
+    assert_eq!(rows[0].total.format(4), "0.0000");
+}
+
+fn written(format: super::Format) -> String {
+    let mut rows = rows();
+    super::sort(&mut rows, super::Sort::default());
+    let mut out = vec![];
+    super::write(&mut out, &rows[..2], format, 2).unwrap();
+    String::from_utf8(out).unwrap()
+}
+
+#[test]
+fn write_csv() {
+    assert_eq!(
+        written(super::Format::Csv),
+        "client,currency,available,held,total,locked\n\
+         1,USD,7.00,0.00,7.00,false\n\
+         2,USD,1.00,0.00,1.00,false\n"
+    );
+}
+
+#[test]
+fn write_json() {
+    assert_eq!(
+        written(super::Format::Json),
+        "[{\"client\":1,\"currency\":\"USD\",\"available\":\"7.00\",\"held\":\"0.00\",\
+         \"total\":\"7.00\",\"locked\":false},\
+         {\"client\":2,\"currency\":\"USD\",\"available\":\"1.00\",\"held\":\"0.00\",\
+         \"total\":\"1.00\",\"locked\":false}]\n"
+    );
+}
+
+#[test]
+fn write_jsonl() {
+    assert_eq!(
+        written(super::Format::Jsonl),
+        "{\"client\":1,\"currency\":\"USD\",\"available\":\"7.00\",\"held\":\"0.00\",\
+         \"total\":\"7.00\",\"locked\":false}\n\
+         {\"client\":2,\"currency\":\"USD\",\"available\":\"1.00\",\"held\":\"0.00\",\
+         \"total\":\"1.00\",\"locked\":false}\n"
+    );
+}
+
+#[test]
+fn rejected_rows_keep_their_fields() {
+    let rejection = crate::parser::Rejection {
+        line: 3,
+        raw: vec![
+            "refund".to_string(),
+            "1".to_string(),
+            "2".to_string(),
+            "".to_string(),
+            "kyc, \"manual\"".to_string(),
+        ],
+        client: Some(1),
+        tx: Some(2),
+        reason: crate::engine::RejectReason::UnknownType("refund".to_string()),
+    };
+    let mut out = vec![];
+    {
+        let mut rejects = super::RejectWriter::new(&mut out).unwrap();
+        rejects.write(&rejection).unwrap();
+        rejects.flush().unwrap();
+    }
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "line,reason,message,raw\n\
+         3,unknown_type,unknown type: refund,refund,1,2,,\"kyc, \"\"manual\"\"\"\n"
+    );
+}
+
+#[test]
+fn write_statement_csv() {
+    let mut engine = crate::Engine::new(Default::default(), Default::default());
+    engine.keep_history(None);
+    let deposit = crate::Operation::Deposit {
+        client: 1,
+        tx: 1,
+        amount: usd("7"),
+    };
+    engine.process(deposit).unwrap();
+    let _ = engine.process(crate::Operation::Resolve {
+        client: 1,
+        tx: 2,
+        amount: None,
+        key: None,
+    });
+    let mut out = vec![];
+    super::write_statement(&mut out, 1, engine.history(1), super::Format::Csv, 2).unwrap();
+    assert_eq!(
+        String::from_utf8(out).unwrap(),
+        "step,type,tx,amount,status,reason,currency,available,held,total,locked,note\n\
+         1,deposit,1,7.00,applied,,USD,7.00,0.00,7.00,false,\n\
+         2,resolve,2,,rejected,unknown_linked_tx,,,,,false,\n"
+    );
+}
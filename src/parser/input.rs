@@ -0,0 +1,163 @@
+use super::InputRow_;
+use serde::Deserialize;
+use std::io::{BufRead, BufReader, Read};
+use std::str::FromStr;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum InputFormat {
+    Csv,
+    /// A json object per line with the same fields of the csv
+    Jsonl,
+}
+
+impl InputFormat {
+    /// Files ending in .jsonl or .ndjson are Jsonl everything else is Csv
+    pub fn from_path(path: &str) -> Self {
+        if path.ends_with(".jsonl") || path.ends_with(".ndjson") {
+            InputFormat::Jsonl
+        } else {
+            InputFormat::Csv
+        }
+    }
+}
+
+impl FromStr for InputFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, String> {
+        match s {
+            "csv" => Ok(InputFormat::Csv),
+            "jsonl" => Ok(InputFormat::Jsonl),
+            _ => Err(format!("unknown input format: {}", s)),
+        }
+    }
+}
+
+/// A row of the input, row is an error if it can not be deserialized
+pub(super) struct Record {
+    pub line: u64,
+    /// Fields of the row as they are in the input
+    pub raw: Vec<String>,
+    pub row: Result<InputRow_, String>,
+}
+
+/// Err if the csv headers can not be read
+pub(super) fn records<R: Read + 'static>(
+    reader: R,
+    format: InputFormat,
+) -> std::io::Result<Box<dyn Iterator<Item = Record>>> {
+    Ok(match format {
+        InputFormat::Csv => Box::new(csv_records(reader)?),
+        InputFormat::Jsonl => Box::new(jsonl_records(reader)),
+    })
+}
+
+fn csv_records<R: Read>(reader: R) -> std::io::Result<impl Iterator<Item = Record>> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(true)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(reader);
+    let headers = rdr.headers()?.clone();
+
+    Ok(rdr.into_records().map(move |result| match result {
+        Err(e) => Record {
+            line: e.position().map(|p| p.line()).unwrap_or(0),
+            raw: vec![],
+            row: Err(e.to_string()),
+        },
+        Ok(record) => Record {
+            line: record.position().map(|p| p.line()).unwrap_or(0),
+            raw: record.iter().map(String::from).collect(),
+            row: record
+                .deserialize(Some(&headers))
+                .map_err(|e: csv::Error| e.to_string()),
+        },
+    }))
+}
+
+// Amounts can be json strings or numbers
+#[derive(Debug, Deserialize)]
+struct JsonRow {
+    #[serde(rename = "type")]
+    type_: String,
+    client: u16,
+    tx: u32,
+    amount: Option<serde_json::Value>,
+    currency: Option<String>,
+    reason: Option<String>,
+    to_client: Option<u16>,
+    key: Option<u32>,
+}
+
+impl JsonRow {
+    fn into_row(self) -> Result<InputRow_, String> {
+        let amount = match self.amount {
+            None | Some(serde_json::Value::Null) => None,
+            Some(serde_json::Value::String(amount)) => Some(amount),
+            Some(serde_json::Value::Number(amount)) => Some(amount.to_string()),
+            Some(amount) => return Err(format!("invalid amount: {}", amount)),
+        };
+        Ok(InputRow_ {
+            type_: self.type_,
+            client: self.client,
+            tx: self.tx,
+            amount,
+            currency: self.currency,
+            reason: self.reason,
+            to_client: self.to_client,
+            key: self.key,
+        })
+    }
+}
+
+fn jsonl_records<R: Read>(reader: R) -> impl Iterator<Item = Record> {
+    BufReader::new(reader)
+        .lines()
+        .enumerate()
+        .map(|(index, line)| (index as u64 + 1, line))
+        .filter(|(_, line)| match line {
+            Ok(line) => !line.trim().is_empty(),
+            Err(_) => true,
+        })
+        .map(|(line, result)| match result {
+            Err(e) => Record {
+                line,
+                raw: vec![],
+                row: Err(e.to_string()),
+            },
+            Ok(text) => Record {
+                line,
+                row: json_line(&text),
+                raw: vec![text],
+            },
+        })
+}
+
+pub(super) fn json_line(line: &str) -> Result<InputRow_, String> {
+    serde_json::from_str::<JsonRow>(line)
+        .map_err(|e| e.to_string())
+        .and_then(JsonRow::into_row)
+}
+
+// Read a single csv record from line
+fn csv_record(line: &str) -> Result<csv::StringRecord, String> {
+    let mut rdr = csv::ReaderBuilder::new()
+        .has_headers(false)
+        .flexible(true)
+        .trim(csv::Trim::All)
+        .from_reader(line.as_bytes());
+    let mut record = csv::StringRecord::new();
+    rdr.read_record(&mut record).map_err(|e| e.to_string())?;
+    Ok(record)
+}
+
+pub(super) fn csv_headers(line: &str) -> Result<csv::StringRecord, String> {
+    csv_record(line)
+}
+
+pub(super) fn csv_line(headers: &csv::StringRecord, line: &str) -> Result<InputRow_, String> {
+    csv_record(line)?
+        .deserialize(Some(headers))
+        .map_err(|e| e.to_string())
+}
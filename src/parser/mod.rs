@@ -1,9 +1,18 @@
-use crate::db::Db;
-use crate::engine::Usd;
-use std::convert::{From, Into, TryFrom, TryInto};
+use crate::api::{Engine, Operation};
+use crate::db::Store;
+use crate::engine::{Amount, Currency, RejectReason};
+use std::convert::{TryFrom, TryInto};
 
 use serde::Deserialize;
 
+mod input;
+mod sharded;
+#[cfg(test)]
+mod test;
+
+pub use input::InputFormat;
+pub use sharded::parse_sharded;
+
 #[derive(Debug, Deserialize)]
 struct InputRow_ {
     #[serde(rename = "type")]
@@ -11,215 +20,271 @@ struct InputRow_ {
     client: u16,
     tx: u32,
     amount: Option<String>,
+    currency: Option<String>,
+    /// Audit reason of admin rows
+    reason: Option<String>,
+    /// Destination of transfer rows
+    to_client: Option<u16>,
+    /// Idempotency key of dispute, resolve and chargeback rows
+    key: Option<u32>,
 }
 
-struct InputRow {
-    type_: String,
-    client: u16,
-    tx: u32,
-    amount: Option<String>,
-    linked_amount: Option<Usd>,
+/// A row that has not been applied, client and tx are None if the row can not be deserialized
+#[derive(Debug)]
+pub struct Rejection {
+    /// Line of the row in the input
+    pub line: u64,
+    /// Fields of the row as they are in the input
+    pub raw: Vec<String>,
+    pub client: Option<u16>,
+    pub tx: Option<u32>,
+    pub reason: RejectReason,
 }
 
-impl From<InputRow_> for InputRow {
-    fn from(input_row: InputRow_) -> Self {
-        InputRow {
-            type_: input_row.type_,
-            client: input_row.client,
-            tx: input_row.tx,
-            amount: input_row.amount,
-            linked_amount: None,
+impl std::fmt::Display for Rejection {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        match (self.client, self.tx) {
+            (Some(client), Some(tx)) => write!(
+                f,
+                "line {} client {} tx {}: {}",
+                self.line, client, tx, self.reason
+            ),
+            (_, _) => write!(f, "line {}: {}", self.line, self.reason),
         }
     }
 }
 
-pub fn parse(path: String, db: &mut Db) {
-    let mut rdr = csv::ReaderBuilder::new()
-        .has_headers(true)
-        .flexible(true)
-        .trim(csv::Trim::All)
-        .from_path(path)
-        .expect("invalid file");
-
-    for result in rdr.deserialize() {
-        let row: Result<InputRow_, csv::Error> = result;
-        match row {
-            Err(e) => eprintln!("WARNING: ingored input {}", e),
-            Ok(row) => {
-                let mut row: InputRow = row.into();
-                if row.type_ == "dispute" || row.type_ == "resolve" || row.type_ == "chargeback" {
-                    let client_id = row.client;
-                    let linked_tx = db.get_tx(row.tx);
-                    match linked_tx {
-                        None => {
-                            eprintln!("WARNING: ingored row invalid linked tx id: {}", row.tx);
-                            continue;
-                        }
-                        Some(linked_tx) => {
-                            if linked_tx.client_id != row.client {
-                                eprintln!(
-                                    "WARNING: ingored row invalid linked tx client id: {}",
-                                    row.client
-                                );
-                                continue;
-                            }
-                            // TODO It assume that they always refer to a deposit
-                            // that is always a Credit so a Debt is needed fo dispute and
-                            // chargeback and a Credit is needed for resolve
-                            if row.type_ == "resolve" {
-                                row.linked_amount = Some(linked_tx.parsed_tx.get_amount())
-                            } else {
-                                row.linked_amount =
-                                    Some((linked_tx.parsed_tx.get_amount() * -1).unwrap())
-                            }
-                        }
-                    }
-                    let parsed_tx: Result<crate::engine::Transaction, ()> = row.try_into();
-                    match parsed_tx {
-                        Err(_) => continue,
-                        Ok(parsed_tx) => {
-                            crate::engine::engine(db, parsed_tx, client_id);
-                        }
-                    }
-                } else {
-                    let client_id = row.client;
-                    let tx_id = row.tx;
-                    let parsed_tx: Result<crate::engine::Transaction, ()> = row.try_into();
-                    match parsed_tx {
-                        Err(_) => continue,
-                        Ok(parsed_tx) => {
-                            if db.has_id(tx_id) {
-                                db.add_tx(tx_id, parsed_tx.clone(), client_id)
-                            }
-                            crate::engine::engine(db, parsed_tx, client_id);
-                        }
-                    }
-                }
-            }
+/// Parse rows a line at a time, for csv the first line is the header. If format is None it
+/// is detected from the first line: jsonl if it starts with '{' csv otherwise
+pub struct LineParser {
+    format: Option<InputFormat>,
+    headers: Option<csv::StringRecord>,
+}
+
+impl LineParser {
+    pub fn new(format: Option<InputFormat>) -> Self {
+        LineParser {
+            format,
+            headers: None,
         }
     }
-}
 
-// It save the id of the transactions that are referenced by special txs
-pub fn pre_parse(path: String, db: &mut Db) {
-    let mut rdr = csv::ReaderBuilder::new()
-        .has_headers(true)
-        .flexible(true)
-        .trim(csv::Trim::All)
-        .from_path(path)
-        .expect("invalid file");
-    for result in rdr.deserialize() {
-        let row: Result<InputRow_, csv::Error> = result;
-        match row {
-            Err(e) => eprintln!("WARNING: ingored input {}", e),
-            Ok(row) => {
-                if row.type_ == "dispute" || row.type_ == "resolve" || row.type_ == "chargeback" {
-                    db.add_id(row.tx);
-                }
-            }
+    /// None for the csv header and for empty lines
+    pub fn parse(&mut self, line: &str) -> Option<Result<Operation, RejectReason>> {
+        let line = line.trim();
+        if line.is_empty() {
+            return None;
         }
+        let format = *self.format.get_or_insert_with(|| {
+            if line.starts_with('{') {
+                InputFormat::Jsonl
+            } else {
+                InputFormat::Csv
+            }
+        });
+        let row = match (format, &self.headers) {
+            (InputFormat::Jsonl, _) => input::json_line(line),
+            (InputFormat::Csv, Some(headers)) => input::csv_line(headers, line),
+            (InputFormat::Csv, None) => match input::csv_headers(line) {
+                Ok(headers) => {
+                    self.headers = Some(headers);
+                    return None;
+                }
+                Err(e) => Err(e),
+            },
+        };
+        Some(
+            row.map_err(RejectReason::Malformed)
+                .and_then(TryInto::try_into),
+        )
     }
 }
 
-impl TryFrom<InputRow> for crate::engine::Transaction {
-    type Error = ();
+/// Identity of the input consumed before any row
+pub const INPUT_HASH: u64 = 0xcbf2_9ce4_8422_2325;
 
-    fn try_from(row: InputRow) -> Result<Self, ()> {
-        if row.type_ == "withdraw" {
-            match row.amount {
-                None => {
-                    eprintln!("WARNING: ingored row no amount: {:#?}", row.amount);
-                    Err(())
-                }
-                Some(amount) => {
-                    let usd = Usd::try_from(&format!("{}{}", "-", amount)[..]);
-                    match usd {
-                        Err(_) => {
-                            eprintln!("WARNING: ingored row invalid amount: {}", amount);
-                            Err(())
-                        }
-                        Ok(usd) => match usd {
-                            merx::Asset::Credit(_) => {
-                                eprintln!("WARNING: ingored row negative amount: {}", amount);
-                                Err(())
-                            }
-                            merx::Asset::Debt(usd) => {
-                                Ok(crate::engine::Transaction::new_withdrawl(usd))
-                            }
-                        },
-                    }
-                }
-            }
-        } else if row.type_ == "deposit" {
-            match row.amount {
-                None => {
-                    eprintln!("WARNING: ingored row no amount: {:#?}", row.amount);
-                    Err(())
-                }
-                Some(amount) => {
-                    let usd = Usd::try_from(&amount[..]);
-                    match usd {
-                        Err(_) => {
-                            eprintln!("WARNING: ingored row invalid amount: {}", amount);
-                            Err(())
-                        }
-                        Ok(usd) => match usd {
-                            merx::Asset::Debt(_) => {
-                                eprintln!("WARNING: ingored row positive amount: {}", amount);
-                                Err(())
-                            }
-                            merx::Asset::Credit(usd) => {
-                                Ok(crate::engine::Transaction::new_deposit(usd))
-                            }
-                        },
-                    }
-                }
+/// Identity of the input after the row with the given fields, a FNV-1a hash of every field
+/// consumed so far. Stable between versions as it is persisted by the store
+pub fn hash_row<'a, I: IntoIterator<Item = &'a str>>(mut hash: u64, fields: I) -> u64 {
+    for field in fields {
+        for byte in field.bytes().chain(std::iter::once(0x1f)) {
+            hash ^= u64::from(byte);
+            hash = hash.wrapping_mul(0x100_0000_01b3);
+        }
+    }
+    hash
+}
+
+/// on_reject is called for every row that has not been applied, rows already committed by
+/// the engine store are skipped. Err if the input can not be read, the store can not commit or
+/// the rows committed by the store are not the first rows of the input
+pub fn parse<R: std::io::Read + 'static, S: Store, F: FnMut(Rejection)>(
+    reader: R,
+    format: InputFormat,
+    engine: &mut Engine<S>,
+    mut on_reject: F,
+) -> std::io::Result<()> {
+    let resume = engine.committed();
+    let mut resumed = resume == 0;
+    let mut input = INPUT_HASH;
+    for record in input::records(reader, format)? {
+        let line = record.line;
+        input = hash_row(input, record.raw.iter().map(String::as_str));
+        if line > 0 && !resumed {
+            if line > resume {
+                return Err(other_input(resume));
             }
-        } else if row.type_ == "dispute" {
-            match row.linked_amount {
-                None => {
-                    eprintln!("WARNING: ingored row no amount: {:#?}", row.amount);
-                    Err(())
+            if line == resume {
+                let committed = engine.committed_input();
+                if committed != 0 && committed != input {
+                    return Err(other_input(resume));
                 }
-                Some(amount) => match amount {
-                    merx::Asset::Credit(_) => {
-                        eprintln!("WARNING: ingored row positive amount: {:#?}", amount);
-                        Err(())
-                    }
-                    merx::Asset::Debt(usd) => Ok(crate::engine::Transaction::new_dispute(usd)),
-                },
+                resumed = true;
             }
-        } else if row.type_ == "resolve" {
-            match row.linked_amount {
-                None => {
-                    eprintln!("WARNING: ingored row no amount: {:#?}", row.amount);
-                    Err(())
-                }
-                Some(amount) => match amount {
-                    merx::Asset::Debt(_) => {
-                        eprintln!("WARNING: ingored row negative amount: {:#?}", amount);
-                        Err(())
+            continue;
+        }
+        match record.row {
+            Err(e) => on_reject(Rejection {
+                line: record.line,
+                raw: record.raw,
+                client: None,
+                tx: None,
+                reason: RejectReason::Malformed(e),
+            }),
+            Ok(row) => {
+                let client = row.client;
+                let tx = row.tx;
+                let outcome = match row.try_into() {
+                    Ok(operation) => engine.process(operation),
+                    Err(reason) => {
+                        engine.record_rejected(client, tx, reason.clone());
+                        Err(reason)
                     }
-                    merx::Asset::Credit(usd) => Ok(crate::engine::Transaction::new_resolve(usd)),
-                },
-            }
-        } else if row.type_ == "chargeback" {
-            match row.linked_amount {
-                None => {
-                    eprintln!("WARNING: ingored row no amount: {:#?}", row.amount);
-                    Err(())
+                };
+                if let Err(reason) = outcome {
+                    on_reject(Rejection {
+                        line: record.line,
+                        raw: record.raw,
+                        client: Some(client),
+                        tx: Some(tx),
+                        reason,
+                    })
                 }
-                Some(amount) => match amount {
-                    merx::Asset::Credit(_) => {
-                        eprintln!("WARNING: ingored row positive amount: {:#?}", amount);
-                        Err(())
-                    }
-                    merx::Asset::Debt(usd) => Ok(crate::engine::Transaction::new_chargeback(usd)),
-                },
             }
-        } else {
-            eprintln!("WARNING: ignored row unknown type: {}", row.type_);
-            Err(())
+        }
+        engine.commit(line, input)?;
+    }
+    if !resumed {
+        return Err(other_input(resume));
+    }
+    Ok(())
+}
+
+fn other_input(line: u64) -> std::io::Error {
+    std::io::Error::new(
+        std::io::ErrorKind::InvalidData,
+        format!(
+            "the store has committed the rows up to line {} of another input",
+            line
+        ),
+    )
+}
+
+// Rows without currency are in USD
+fn parse_currency(currency: &Option<String>) -> Result<Currency, RejectReason> {
+    match currency {
+        None => Ok(Currency::default()),
+        Some(currency) => currency
+            .parse()
+            .map_err(|_| RejectReason::UnknownCurrency(currency.clone())),
+    }
+}
+
+fn parse_reason(row: &InputRow_) -> Result<String, RejectReason> {
+    match row.reason.as_ref().map(|reason| reason.trim()) {
+        None | Some("") => Err(RejectReason::MissingReason),
+        Some(reason) => Ok(reason.to_string()),
+    }
+}
+
+// The amount of dispute, resolve and chargeback rows is optional
+fn parse_partial_amount(row: &InputRow_) -> Result<Option<Amount>, RejectReason> {
+    match &row.amount {
+        Some(amount) if !amount.trim().is_empty() => parse_amount(row).map(Some),
+        _ => Ok(None),
+    }
+}
+
+// Parse an amount that must not be negative
+fn parse_amount(row: &InputRow_) -> Result<Amount, RejectReason> {
+    let currency = parse_currency(&row.currency)?;
+    match &row.amount {
+        None => Err(RejectReason::MissingAmount),
+        Some(amount) => match Amount::parse(currency, &amount[..]) {
+            None => Err(RejectReason::InvalidAmount(amount.clone())),
+            Some(parsed) if parsed.is_debt() => Err(RejectReason::NegativeAmount(amount.clone())),
+            Some(parsed) => Ok(parsed),
+        },
+    }
+}
+
+impl TryFrom<InputRow_> for Operation {
+    type Error = RejectReason;
+
+    fn try_from(row: InputRow_) -> Result<Self, RejectReason> {
+        let client = row.client;
+        let tx = row.tx;
+        match &row.type_[..] {
+            "deposit" => Ok(Operation::Deposit {
+                client,
+                tx,
+                amount: parse_amount(&row)?,
+            }),
+            "withdraw" => Ok(Operation::Withdrawal {
+                client,
+                tx,
+                amount: parse_amount(&row)?,
+            }),
+            "dispute" => Ok(Operation::Dispute {
+                client,
+                tx,
+                amount: parse_partial_amount(&row)?,
+                key: row.key,
+            }),
+            "resolve" => Ok(Operation::Resolve {
+                client,
+                tx,
+                amount: parse_partial_amount(&row)?,
+                key: row.key,
+            }),
+            "chargeback" => Ok(Operation::Chargeback {
+                client,
+                tx,
+                amount: parse_partial_amount(&row)?,
+                key: row.key,
+            }),
+            "transfer" => Ok(Operation::Transfer {
+                client,
+                to_client: row.to_client.ok_or(RejectReason::MissingToClient)?,
+                tx,
+                amount: parse_amount(&row)?,
+            }),
+            "freeze" => Ok(Operation::Freeze {
+                client,
+                tx,
+                reason: parse_reason(&row)?,
+            }),
+            "unfreeze" => Ok(Operation::Unfreeze {
+                client,
+                tx,
+                reason: parse_reason(&row)?,
+            }),
+            "close" => Ok(Operation::Close {
+                client,
+                tx,
+                reason: parse_reason(&row)?,
+            }),
+            _ => Err(RejectReason::UnknownType(row.type_)),
         }
     }
 }
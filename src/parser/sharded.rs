@@ -0,0 +1,223 @@
+use super::input::{self, InputFormat};
+use super::{InputRow_, Rejection};
+use crate::api::{Engine, Operation};
+use crate::db::IdSet;
+use crate::engine::{DuplicatePolicy, RejectReason};
+use std::collections::HashMap;
+use std::convert::TryInto;
+use std::sync::{mpsc, Arc, Mutex};
+
+// Rows buffered for every worker before the reader blocks
+const QUEUE: usize = 1024;
+
+// A row routed to a worker
+struct Job {
+    line: u64,
+    raw: Vec<String>,
+    operation: Operation,
+}
+
+enum Message {
+    Job(Job),
+    // Sent back once the jobs before it have been applied
+    Drained(mpsc::Sender<()>),
+}
+
+/// Like parse but the rows are applied by a thread per engine, a client always goes to the
+/// same engine so its rows are applied in input order, the outcome of every row is the same
+/// of parse. A tx id already used in the input is checked against all the engines once they
+/// have applied the rows before it, with the duplicate policy of the first one. A dispute of a
+/// tx of a client in another engine is rejected here as client_mismatch. Transfers between
+/// clients of different engines are applied by the calling thread once both engines have
+/// applied the rows before them. on_reject is called once every row has been applied, ordered
+/// by line. Err if the input can not be read
+pub fn parse_sharded<R: std::io::Read + 'static, F: FnMut(Rejection)>(
+    reader: R,
+    format: InputFormat,
+    engines: Vec<Engine>,
+    mut on_reject: F,
+) -> std::io::Result<Vec<Engine>> {
+    assert!(!engines.is_empty(), "no engines");
+    let records = input::records(reader, format)?;
+    let duplicates = engines[0].duplicates();
+    let engines: Vec<_> = engines
+        .into_iter()
+        .map(|engine| Arc::new(Mutex::new(engine)))
+        .collect();
+    let (senders, workers): (Vec<_>, Vec<_>) = engines
+        .iter()
+        .map(|engine| {
+            let engine = engine.clone();
+            let (sender, receiver) = mpsc::sync_channel::<Message>(QUEUE);
+            (sender, std::thread::spawn(move || work(&engine, receiver)))
+        })
+        .unzip();
+
+    let mut rejections = vec![];
+    // Ids sent to the engines, maybe not applied
+    let mut ids = IdSet::default();
+    // Client of the last deposit or withdrawal sent for a tx id
+    let mut owners: HashMap<u32, u16> = HashMap::new();
+    for record in records {
+        let line = record.line;
+        let raw = record.raw;
+        let row: InputRow_ = match record.row {
+            Ok(row) => row,
+            Err(e) => {
+                rejections.push(Rejection {
+                    line,
+                    raw,
+                    client: None,
+                    tx: None,
+                    reason: RejectReason::Malformed(e),
+                });
+                continue;
+            }
+        };
+        let client = row.client;
+        let tx = row.tx;
+        let reject = |reason| Rejection {
+            line,
+            raw: raw.clone(),
+            client: Some(client),
+            tx: Some(tx),
+            reason,
+        };
+        let operation: Operation = match row.try_into() {
+            Ok(operation) => operation,
+            Err(reason) => {
+                rejections.push(reject(reason));
+                continue;
+            }
+        };
+        let (reserved, linked) = match operation {
+            Operation::Deposit { .. }
+            | Operation::Withdrawal { .. }
+            | Operation::Transfer { .. } => (Some(tx), false),
+            Operation::Dispute { key, .. }
+            | Operation::Resolve { key, .. }
+            | Operation::Chargeback { key, .. } => (key, true),
+            _ => (None, false),
+        };
+        if let Some(id) = reserved {
+            // The id is a duplicate only if an engine has applied it
+            if ids.contains(id) {
+                drain(senders.iter());
+                let applied = engines
+                    .iter()
+                    .any(|engine| engine.lock().expect("engine poisoned").has_id(id));
+                if applied {
+                    if duplicates == DuplicatePolicy::Reject {
+                        rejections.push(reject(RejectReason::DuplicateTx(id)));
+                    }
+                    continue;
+                }
+            }
+            ids.insert(id);
+        }
+        let worker = client as usize % senders.len();
+        match operation {
+            Operation::Deposit { .. } | Operation::Withdrawal { .. } => {
+                owners.insert(tx, client);
+            }
+            _ => (),
+        }
+        // The tx is only in the engine of its client
+        let owner_worker = match owners.get(&tx) {
+            Some(owner) if linked => Some(*owner as usize % senders.len()),
+            _ => None,
+        };
+        if let Some(owner_worker) = owner_worker.filter(|owner| *owner != worker) {
+            drain(vec![&senders[owner_worker]]);
+            let stored = engines[owner_worker]
+                .lock()
+                .expect("engine poisoned")
+                .tx_client(tx);
+            if let Some(owner) = stored {
+                rejections.push(reject(RejectReason::ClientMismatch(owner)));
+                continue;
+            }
+        }
+        if let Operation::Transfer { to_client, .. } = operation {
+            let to_worker = to_client as usize % senders.len();
+            if to_worker != worker {
+                drain(vec![&senders[worker], &senders[to_worker]]);
+                let mut from = engines[worker].lock().expect("engine poisoned");
+                let mut to = engines[to_worker].lock().expect("engine poisoned");
+                if let Err(reason) = from.process_across(&mut to, operation) {
+                    rejections.push(reject(reason));
+                }
+                continue;
+            }
+        }
+        let job = Job {
+            line,
+            raw,
+            operation,
+        };
+        senders[worker]
+            .send(Message::Job(job))
+            .expect("worker stopped");
+    }
+    drop(senders);
+
+    for worker in workers {
+        rejections.extend(worker.join().expect("worker panicked"));
+    }
+    let engines = engines
+        .into_iter()
+        .map(|engine| match Arc::try_unwrap(engine) {
+            Ok(engine) => engine.into_inner().expect("engine poisoned"),
+            Err(_) => unreachable!("workers stopped"),
+        })
+        .collect();
+    rejections.sort_by_key(|rejection| rejection.line);
+    for rejection in rejections {
+        on_reject(rejection);
+    }
+    Ok(engines)
+}
+
+// Wait until the workers have applied every job sent to them
+fn drain<'a, I: IntoIterator<Item = &'a mpsc::SyncSender<Message>>>(senders: I) {
+    let (drained, done) = mpsc::channel();
+    let mut workers = 0;
+    for sender in senders {
+        let message = Message::Drained(drained.clone());
+        sender.send(message).expect("worker stopped");
+        workers += 1;
+    }
+    drop(drained);
+    for _ in 0..workers {
+        done.recv().expect("worker stopped");
+    }
+}
+
+fn work(engine: &Mutex<Engine>, messages: mpsc::Receiver<Message>) -> Vec<Rejection> {
+    let mut rejections = vec![];
+    for message in messages {
+        let job = match message {
+            Message::Job(job) => job,
+            Message::Drained(drained) => {
+                let _ = drained.send(());
+                continue;
+            }
+        };
+        let client = job.operation.client();
+        let tx = job.operation.tx();
+        let outcome = engine
+            .lock()
+            .expect("engine poisoned")
+            .process(job.operation);
+        if let Err(reason) = outcome {
+            rejections.push(Rejection {
+                line: job.line,
+                raw: job.raw,
+                client: Some(client),
+                tx: Some(tx),
+                reason,
+            });
+        }
+    }
+    rejections
+}
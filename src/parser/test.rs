@@ -0,0 +1,242 @@
+use super::input::{records, InputFormat};
+
+#[test]
+fn csv_and_jsonl_produce_the_same_rows() {
+    let csv = "type,client,tx,amount,currency\ndeposit,1,1,1.5,EUR\ndispute,1,1,,\n";
+    let jsonl = "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":1.5,\"currency\":\"EUR\"}\n\n{\"type\":\"dispute\",\"client\":1,\"tx\":1}\n";
+    let csv: Vec<_> = records(std::io::Cursor::new(csv), InputFormat::Csv)
+        .unwrap()
+        .map(|record| format!("{:?}", record.row.unwrap()))
+        .collect();
+    let jsonl: Vec<_> = records(std::io::Cursor::new(jsonl), InputFormat::Jsonl)
+        .unwrap()
+        .map(|record| format!("{:?}", record.row.unwrap()))
+        .collect();
+    assert_eq!(csv, jsonl);
+}
+
+#[test]
+fn jsonl_lines_are_numbered_from_one() {
+    let jsonl = "{\"type\":\"deposit\",\"client\":1,\"tx\":1,\"amount\":\"1\"}\n\nnot json\n";
+    let lines: Vec<_> = records(std::io::Cursor::new(jsonl), InputFormat::Jsonl)
+        .unwrap()
+        .map(|record| (record.line, record.row.is_ok()))
+        .collect();
+    assert_eq!(lines, vec![(1, true), (3, false)]);
+}
+
+#[test]
+fn input_format_from_path() {
+    assert_eq!(InputFormat::from_path("txs.jsonl"), InputFormat::Jsonl);
+    assert_eq!(InputFormat::from_path("txs.ndjson"), InputFormat::Jsonl);
+    assert_eq!(InputFormat::from_path("txs.csv"), InputFormat::Csv);
+}
+
+fn clients_of(engine: &crate::Engine) -> Vec<String> {
+    let mut clients: Vec<String> = engine
+        .clients()
+        .map(|(id, client)| format!("{} {:?}", id, client))
+        .collect();
+    clients.sort();
+    clients
+}
+
+#[test]
+fn sharded_parse_is_the_same_of_parse() {
+    use crate::engine::DisputePolicy;
+    use crate::Engine;
+
+    let mut csv = String::from("type,client,tx,amount,to_client\n");
+    for tx in 0..200u32 {
+        let client = tx / 5 % 7;
+        csv.push_str(&match tx % 5 {
+            0 | 1 => format!("deposit,{},{},{}.5,\n", client, tx, tx),
+            2 => format!("withdraw,{},{},{},\n", client, tx, tx / 2),
+            3 => format!("dispute,{},{},,\n", client, tx - 3),
+            _ => format!("chargeback,{},{},,\n", client, tx - 4),
+        });
+        // Transfers to clients of the same and of other workers, some to locked accounts
+        if tx % 5 == 2 {
+            let to_client = (client + tx / 10) % 7;
+            let amount = tx % 13;
+            csv.push_str(&format!(
+                "transfer,{},{},{},{}\n",
+                client,
+                1000 + tx,
+                amount,
+                to_client
+            ));
+        }
+    }
+    csv.push_str("deposit,1,1,1,\ntransfer,2,1002,1,3\n");
+    // Disputes of a tx of a client of another worker and an id retried after a failure
+    csv.push_str("dispute,1,0,,\nwithdraw,8,5000,1,\ndeposit,8,5000,1,\n");
+    csv.push_str("deposit,9,5000,1,\ndispute,9,5000,,\n");
+
+    let new_engine = || Engine::new(DisputePolicy::default(), Default::default());
+    let mut engine = new_engine();
+    let mut expected = vec![];
+    super::parse(
+        std::io::Cursor::new(csv.clone()),
+        InputFormat::Csv,
+        &mut engine,
+        |rejection| expected.push((rejection.line, rejection.reason)),
+    )
+    .unwrap();
+
+    let mut rejections = vec![];
+    let engines = super::parse_sharded(
+        std::io::Cursor::new(csv),
+        InputFormat::Csv,
+        vec![new_engine(), new_engine(), new_engine()],
+        |rejection| rejections.push((rejection.line, rejection.reason)),
+    )
+    .unwrap();
+
+    let mut clients: Vec<String> = engines.iter().flat_map(clients_of).collect();
+    clients.sort();
+    assert_eq!(clients, clients_of(&engine));
+    assert_eq!(rejections, expected);
+}
+
+#[test]
+fn line_parser_detects_the_format() {
+    use super::LineParser;
+    use crate::engine::RejectReason;
+    use crate::Operation;
+
+    let mut csv = LineParser::new(None);
+    assert!(csv.parse("type, client, tx, amount").is_none());
+    assert!(csv.parse("").is_none());
+    assert_eq!(
+        csv.parse("dispute, 2, 3,").unwrap(),
+        Ok(Operation::Dispute {
+            client: 2,
+            tx: 3,
+            amount: None,
+            key: None
+        })
+    );
+    assert_eq!(
+        csv.parse("refund, 2, 3,").unwrap(),
+        Err(RejectReason::UnknownType("refund".to_string()))
+    );
+
+    let mut jsonl = LineParser::new(None);
+    assert_eq!(
+        jsonl
+            .parse("{\"type\": \"resolve\", \"client\": 2, \"tx\": 3}")
+            .unwrap(),
+        Ok(Operation::Resolve {
+            client: 2,
+            tx: 3,
+            amount: None,
+            key: None
+        })
+    );
+    assert!(matches!(
+        jsonl.parse("type,client,tx,amount"),
+        Some(Err(RejectReason::Malformed(_)))
+    ));
+}
+
+#[test]
+fn admin_rows_need_a_reason() {
+    use super::LineParser;
+    use crate::engine::RejectReason;
+    use crate::Operation;
+
+    let mut csv = LineParser::new(None);
+    assert!(csv.parse("type,client,tx,amount,reason").is_none());
+    assert_eq!(
+        csv.parse("freeze,2,3,,kyc review").unwrap(),
+        Ok(Operation::Freeze {
+            client: 2,
+            tx: 3,
+            reason: "kyc review".to_string()
+        })
+    );
+    assert_eq!(
+        csv.parse("close,2,4,, ").unwrap(),
+        Err(RejectReason::MissingReason)
+    );
+
+    let mut jsonl = LineParser::new(None);
+    assert_eq!(
+        jsonl
+            .parse("{\"type\": \"unfreeze\", \"client\": 2, \"tx\": 5}")
+            .unwrap(),
+        Err(RejectReason::MissingReason)
+    );
+}
+
+#[test]
+fn transfer_rows_need_a_destination() {
+    use super::LineParser;
+    use crate::engine::{Amount, Currency, RejectReason};
+    use crate::Operation;
+
+    let mut csv = LineParser::new(None);
+    assert!(csv.parse("type,client,tx,amount,to_client").is_none());
+    assert_eq!(
+        csv.parse("transfer,1,2,3.5,4").unwrap(),
+        Ok(Operation::Transfer {
+            client: 1,
+            to_client: 4,
+            tx: 2,
+            amount: Amount::parse(Currency::Usd, "3.5").unwrap(),
+        })
+    );
+    assert_eq!(
+        csv.parse("transfer,1,3,3.5,").unwrap(),
+        Err(RejectReason::MissingToClient)
+    );
+}
+
+#[test]
+fn unreadable_headers_are_an_error() {
+    let mut engine = crate::Engine::new(Default::default(), Default::default());
+    let input = std::io::Cursor::new(b"type,cli\xffent\n".to_vec());
+    let result = super::parse(input, InputFormat::Csv, &mut engine, |_| ());
+    assert!(result.is_err());
+}
+
+#[test]
+fn resume_only_the_committed_input() {
+    use crate::db::{DurableDb, Limits};
+
+    let dir = std::env::temp_dir().join(format!("pinoedino-resume-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    let first = "type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,2.0\n";
+    let parse = |input: &str| {
+        let store = DurableDb::open(&dir, Limits::default(), 1 << 20).unwrap();
+        let mut engine = crate::Engine::with_store(store, Default::default());
+        let input = std::io::Cursor::new(input.as_bytes().to_vec());
+        super::parse(input, InputFormat::Csv, &mut engine, |_| ()).map(|()| engine.committed())
+    };
+    assert_eq!(parse(first).unwrap(), 3);
+    // The committed rows are skipped and the new ones applied
+    assert_eq!(parse(&format!("{}deposit,1,3,3.0\n", first)).unwrap(), 4);
+    assert!(parse("type,client,tx,amount\ndeposit,1,1,1.0\ndeposit,1,2,9.0\ndeposit,1,3,3.0\ndeposit,1,4,4.0\n").is_err());
+    assert!(parse(first).is_err());
+    std::fs::remove_dir_all(&dir).unwrap();
+}
+
+#[test]
+fn partial_rows_can_have_a_key() {
+    use super::LineParser;
+    use crate::engine::{Amount, Currency};
+    use crate::Operation;
+
+    let mut csv = LineParser::new(None);
+    assert!(csv.parse("type,client,tx,amount,key").is_none());
+    assert_eq!(
+        csv.parse("dispute,1,2,3,9").unwrap(),
+        Ok(Operation::Dispute {
+            client: 1,
+            tx: 2,
+            amount: Some(Amount::parse(Currency::Usd, "3").unwrap()),
+            key: Some(9),
+        })
+    );
+}
@@ -0,0 +1,253 @@
+use super::{apply, blocking, Ack};
+use crate::api::Engine;
+use crate::db::Store;
+use crate::output::{OutputRecord, OutputRow};
+use crate::parser::{InputFormat, LineParser};
+use serde::Serialize;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+// Bigger bodies are rejected, a body is a single row
+const MAX_BODY: usize = 64 * 1024;
+// Bigger request lines plus headers are rejected
+const MAX_HEAD: u64 = 8 * 1024;
+// A request must be read within it
+const READ_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(10);
+
+pub(super) struct Request {
+    method: String,
+    path: String,
+    content_type: Option<String>,
+    body: Vec<u8>,
+}
+
+pub(super) struct Response {
+    pub(super) status: u16,
+    body: Vec<u8>,
+}
+
+#[derive(Serialize)]
+struct Error {
+    error: String,
+}
+
+impl Response {
+    fn json<T: Serialize>(status: u16, value: &T) -> Self {
+        Response {
+            status,
+            body: serde_json::to_vec(value).expect("serializable"),
+        }
+    }
+
+    fn error(status: u16, error: &str) -> Self {
+        Response::json(
+            status,
+            &Error {
+                error: error.to_string(),
+            },
+        )
+    }
+
+    fn reason(&self) -> &'static str {
+        match self.status {
+            200 => "OK",
+            400 => "Bad Request",
+            404 => "Not Found",
+            405 => "Method Not Allowed",
+            408 => "Request Timeout",
+            413 => "Payload Too Large",
+            422 => "Unprocessable Entity",
+            431 => "Request Header Fields Too Large",
+            _ => "Internal Server Error",
+        }
+    }
+}
+
+/// Serve until shutdown completes:
+/// - GET /clients a json array with the balances of every client as in the output
+/// - GET /clients/{id} the balances of a client, 404 if it has never been seen
+/// - POST /transactions apply the row in the body, a json object or a csv header and row,
+///   respond with an ack, 422 if the row is rejected
+///
+/// Every connection serves a single request
+pub async fn serve_http<S, F>(
+    listener: TcpListener,
+    engine: Arc<Mutex<Engine<S>>>,
+    decimals: u32,
+    shutdown: F,
+) -> std::io::Result<()>
+where
+    S: Store + Send + 'static,
+    F: Future<Output = ()>,
+{
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => return Ok(()),
+            accepted = listener.accept() => {
+                let (stream, _) = accepted?;
+                let engine = engine.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle(stream, engine, decimals).await {
+                        eprintln!("WARNING: http connection closed: {}", e);
+                    }
+                });
+            }
+        }
+    }
+}
+
+async fn handle<S: Store + Send + 'static>(
+    stream: TcpStream,
+    engine: Arc<Mutex<Engine<S>>>,
+    decimals: u32,
+) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let request = tokio::time::timeout(READ_TIMEOUT, read_request(BufReader::new(reader)))
+        .await
+        .unwrap_or_else(|_| Ok(Err(Response::error(408, "request timeout"))))?;
+    let response = match request {
+        Err(response) => response,
+        Ok(request) => blocking(move || route(request, &engine, decimals)).await?,
+    };
+    let head = format!(
+        "HTTP/1.1 {} {}\r\nContent-Type: application/json\r\nContent-Length: {}\r\nConnection: close\r\n\r\n",
+        response.status,
+        response.reason(),
+        response.body.len()
+    );
+    writer.write_all(head.as_bytes()).await?;
+    writer.write_all(&response.body).await?;
+    writer.shutdown().await
+}
+
+// Err is the response for an invalid request
+pub(super) async fn read_request<R: tokio::io::AsyncBufRead + Unpin>(
+    mut reader: R,
+) -> std::io::Result<Result<Request, Response>> {
+    let mut head = (&mut reader).take(MAX_HEAD);
+    let mut line = String::new();
+    if !read_head_line(&mut head, &mut line).await? {
+        return Ok(Err(Response::error(431, "request head too large")));
+    }
+    let mut parts = line.split_whitespace();
+    let (method, path) = match (parts.next(), parts.next()) {
+        (Some(method), Some(path)) => (method.to_string(), path.to_string()),
+        _ => return Ok(Err(Response::error(400, "invalid request line"))),
+    };
+
+    let mut content_length = 0;
+    let mut content_type = None;
+    loop {
+        line.clear();
+        if !read_head_line(&mut head, &mut line).await? {
+            return Ok(Err(Response::error(431, "request head too large")));
+        }
+        if line.trim().is_empty() {
+            break;
+        }
+        let mut header = line.splitn(2, ':');
+        if let (Some(name), Some(value)) = (header.next(), header.next()) {
+            let value = value.trim();
+            match &name.trim().to_ascii_lowercase()[..] {
+                "content-length" => match value.parse() {
+                    Ok(length) => content_length = length,
+                    Err(_) => return Ok(Err(Response::error(400, "invalid content-length"))),
+                },
+                "content-type" => content_type = Some(value.to_ascii_lowercase()),
+                _ => (),
+            }
+        }
+    }
+    if content_length > MAX_BODY {
+        return Ok(Err(Response::error(413, "body too large")));
+    }
+    let mut body = vec![0; content_length];
+    reader.read_exact(&mut body).await?;
+    Ok(Ok(Request {
+        method,
+        path,
+        content_type,
+        body,
+    }))
+}
+
+// false if the line is cut by the limit of head
+async fn read_head_line<R: tokio::io::AsyncBufRead + Unpin>(
+    head: &mut tokio::io::Take<R>,
+    line: &mut String,
+) -> std::io::Result<bool> {
+    head.read_line(line).await?;
+    Ok(line.ends_with('\n') || head.limit() > 0)
+}
+
+fn route<S: Store>(request: Request, engine: &Mutex<Engine<S>>, decimals: u32) -> Response {
+    let segments: Vec<&str> = request.path.trim_matches('/').split('/').collect();
+    match (&request.method[..], &segments[..]) {
+        ("GET", ["clients"]) => {
+            let engine = engine.lock().expect("engine poisoned");
+            let mut rows: Vec<OutputRow> = engine
+                .clients()
+                .flat_map(|(id, client)| OutputRow::from_client(id, client))
+                .collect();
+            crate::output::sort(&mut rows, Default::default());
+            Response::json(200, &records(&rows, decimals))
+        }
+        ("GET", ["clients", id]) => {
+            let id: u16 = match id.parse() {
+                Ok(id) => id,
+                Err(_) => return Response::error(400, "invalid client id"),
+            };
+            match engine.lock().expect("engine poisoned").client(id) {
+                None => Response::error(404, "unknown client"),
+                Some(client) => Response::json(
+                    200,
+                    &records(&OutputRow::from_client(id, &client), decimals),
+                ),
+            }
+        }
+        ("POST", ["transactions"]) => submit(&request, engine),
+        (_, ["clients"]) | (_, ["clients", _]) | (_, ["transactions"]) => {
+            Response::error(405, "method not allowed")
+        }
+        _ => Response::error(404, "not found"),
+    }
+}
+
+fn records(rows: &[OutputRow], decimals: u32) -> Vec<OutputRecord> {
+    rows.iter().map(|row| row.to_record(decimals)).collect()
+}
+
+fn submit<S: Store>(request: &Request, engine: &Mutex<Engine<S>>) -> Response {
+    let body = match std::str::from_utf8(&request.body) {
+        Ok(body) => body,
+        Err(_) => return Response::error(400, "body is not utf-8"),
+    };
+    let format = match &request.content_type {
+        Some(content_type) if content_type.starts_with("application/json") => {
+            Some(InputFormat::Jsonl)
+        }
+        Some(content_type) if content_type.starts_with("text/csv") => Some(InputFormat::Csv),
+        _ => None,
+    };
+    let mut parser = LineParser::new(format);
+    let mut rows = body
+        .lines()
+        .enumerate()
+        .filter_map(|(index, line)| parser.parse(line).map(|row| (index as u64 + 1, row)));
+    let (line, row) = match (rows.next(), rows.next()) {
+        (Some(row), None) => row,
+        _ => return Response::error(400, "the body must contain a single row"),
+    };
+    let outcome = match row {
+        Err(reason) => Err(reason),
+        Ok(operation) => match apply(engine, operation, body) {
+            Ok(outcome) => outcome,
+            Err(e) => return Response::json(500, &Ack::failed(line, &e)),
+        },
+    };
+    let status = if outcome.is_ok() { 200 } else { 422 };
+    Response::json(status, &Ack::new(line, outcome))
+}
@@ -0,0 +1,203 @@
+use crate::api::{Engine, Outcome};
+use crate::db::Store;
+use crate::engine::RejectReason;
+use crate::parser::{hash_row, InputFormat, LineParser, INPUT_HASH};
+use serde::Serialize;
+use std::future::Future;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufRead, AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+mod http;
+#[cfg(test)]
+mod test;
+
+pub use http::serve_http;
+
+// Longer rows are acked as malformed
+const MAX_ROW: u64 = 4 * 1024;
+
+/// Sent back as a json line for every row of a connection
+#[derive(Debug, Serialize)]
+pub struct Ack {
+    /// Line of the row in the connection, the csv header is line 1
+    line: u64,
+    /// accepted, rejected or failed if the row can not be committed
+    status: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    reason: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    message: Option<String>,
+}
+
+impl Ack {
+    fn new(line: u64, outcome: Outcome) -> Self {
+        match outcome {
+            Ok(()) => Ack {
+                line,
+                status: "accepted",
+                reason: None,
+                message: None,
+            },
+            Err(reason) => Ack {
+                line,
+                status: "rejected",
+                reason: Some(reason.code()),
+                message: Some(reason.to_string()),
+            },
+        }
+    }
+
+    // The row can be applied in memory but it is lost on restart, as the rows after it: the
+    // server must be restarted from the store before they are sent again
+    fn failed(line: u64, error: &std::io::Error) -> Self {
+        Ack {
+            line,
+            status: "failed",
+            reason: Some("store_error"),
+            message: Some(format!(
+                "can not commit the row, restart the server before sending it again: {}",
+                error
+            )),
+        }
+    }
+}
+
+/// Apply the rows streamed by every connection to engine until shutdown completes. Every
+/// connection is a csv (header included) or json lines stream, if format is None it is detected
+/// from its first line
+pub async fn serve<S, F>(
+    listener: TcpListener,
+    engine: Arc<Mutex<Engine<S>>>,
+    format: Option<InputFormat>,
+    shutdown: F,
+) -> std::io::Result<()>
+where
+    S: Store + Send + 'static,
+    F: Future<Output = ()>,
+{
+    tokio::pin!(shutdown);
+    loop {
+        tokio::select! {
+            _ = &mut shutdown => return Ok(()),
+            accepted = listener.accept() => {
+                let (stream, _) = accepted?;
+                let engine = engine.clone();
+                tokio::spawn(async move {
+                    if let Err(e) = handle(stream, engine, format).await {
+                        eprintln!("WARNING: connection closed: {}", e);
+                    }
+                });
+            }
+        }
+    }
+}
+
+// Rows of different connections are committed in the order they are applied, text is the
+// row as received. Blocks on the engine lock and on the store, call it from a blocking task
+fn apply<S: Store>(
+    engine: &Mutex<Engine<S>>,
+    operation: crate::api::Operation,
+    text: &str,
+) -> std::io::Result<Outcome> {
+    let mut engine = engine.lock().expect("engine poisoned");
+    let outcome = engine.process(operation);
+    let committed = engine.committed() + 1;
+    let input = match engine.committed_input() {
+        0 => INPUT_HASH,
+        input => input,
+    };
+    engine.commit(committed, hash_row(input, std::iter::once(text)))?;
+    Ok(outcome)
+}
+
+async fn handle<S: Store + Send + 'static>(
+    stream: TcpStream,
+    engine: Arc<Mutex<Engine<S>>>,
+    format: Option<InputFormat>,
+) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut parser = LineParser::new(format);
+    let mut line = 0;
+    while let Some(text) = read_row(&mut reader).await? {
+        line += 1;
+        let outcome = match text.map(|text| (parser.parse(&text), text)) {
+            Err(e) => Ok(Err(RejectReason::Malformed(e))),
+            Ok((None, _)) => continue,
+            Ok((Some(Err(reason)), _)) => Ok(Err(reason)),
+            Ok((Some(Ok(operation)), text)) => {
+                let engine = engine.clone();
+                blocking(move || apply(&engine, operation, &text)).await?
+            }
+        };
+        let (ack, failed) = match outcome {
+            Ok(outcome) => (Ack::new(line, outcome), None),
+            Err(e) => (Ack::failed(line, &e), Some(e)),
+        };
+        let mut ack = serde_json::to_vec(&ack)?;
+        ack.push(b'\n');
+        writer.write_all(&ack).await?;
+        // The rows after a failed one can not be committed either
+        if let Some(e) = failed {
+            return Err(e);
+        }
+    }
+    Ok(())
+}
+
+// Next line without the line end, Err if it is longer than MAX_ROW or not utf-8. None at the end
+// of the stream
+async fn read_row<R: AsyncBufRead + Unpin>(
+    reader: &mut R,
+) -> std::io::Result<Option<Result<String, String>>> {
+    let mut row = vec![];
+    if (&mut *reader)
+        .take(MAX_ROW + 1)
+        .read_until(b'\n', &mut row)
+        .await?
+        == 0
+    {
+        return Ok(None);
+    }
+    if row.last() == Some(&b'\n') {
+        row.pop();
+        if row.last() == Some(&b'\r') {
+            row.pop();
+        }
+    } else if row.len() as u64 > MAX_ROW {
+        skip_line(reader).await?;
+        return Ok(Some(Err(format!("row longer than {} bytes", MAX_ROW))));
+    }
+    Ok(Some(
+        String::from_utf8(row).map_err(|_| "row is not utf-8".to_string()),
+    ))
+}
+
+// Discard the rest of the current line
+async fn skip_line<R: AsyncBufRead + Unpin>(reader: &mut R) -> std::io::Result<()> {
+    loop {
+        let buffer = reader.fill_buf().await?;
+        if buffer.is_empty() {
+            return Ok(());
+        }
+        match buffer.iter().position(|byte| *byte == b'\n') {
+            Some(end) => {
+                reader.consume(end + 1);
+                return Ok(());
+            }
+            None => {
+                let read = buffer.len();
+                reader.consume(read);
+            }
+        }
+    }
+}
+
+async fn blocking<T: Send + 'static, F: FnOnce() -> T + Send + 'static>(
+    f: F,
+) -> std::io::Result<T> {
+    tokio::task::spawn_blocking(f)
+        .await
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::Other, e))
+}
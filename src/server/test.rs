@@ -0,0 +1,193 @@
+use super::serve;
+use crate::engine::{Currency, DisputePolicy};
+use crate::Engine;
+use std::sync::{Arc, Mutex};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+
+// Send rows on a new connection and return the acks
+async fn send(addr: std::net::SocketAddr, rows: &str) -> Vec<String> {
+    let stream = TcpStream::connect(addr).await.unwrap();
+    let (reader, mut writer) = stream.into_split();
+    writer.write_all(rows.as_bytes()).await.unwrap();
+    writer.shutdown().await.unwrap();
+    let mut lines = BufReader::new(reader).lines();
+    let mut acks = vec![];
+    while let Some(ack) = lines.next_line().await.unwrap() {
+        acks.push(ack);
+    }
+    acks
+}
+
+#[tokio::test]
+async fn connections_share_the_engine() {
+    let engine = Arc::new(Mutex::new(Engine::new(
+        DisputePolicy::default(),
+        Default::default(),
+    )));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(serve(listener, engine.clone(), None, async {
+        stopped.await.unwrap();
+    }));
+
+    let csv = send(
+        addr,
+        "type,client,tx,amount\ndeposit,1,1,10\nwithdraw,1,2,11\n",
+    )
+    .await;
+    assert_eq!(
+        csv,
+        vec![
+            r#"{"line":2,"status":"accepted"}"#,
+            r#"{"line":3,"status":"rejected","reason":"insufficient_funds","message":"insufficient funds"}"#,
+        ]
+    );
+    let jsonl = send(
+        addr,
+        "{\"type\":\"withdraw\",\"client\":1,\"tx\":3,\"amount\":\"4\"}\n\n{\"type\":\"deposit\"}\n",
+    )
+    .await;
+    assert_eq!(jsonl.len(), 2);
+    assert_eq!(jsonl[0], r#"{"line":1,"status":"accepted"}"#);
+    assert!(jsonl[1].starts_with(r#"{"line":3,"status":"rejected","reason":"malformed""#));
+    // The rest of a long row is skipped
+    let long = format!(
+        "{{\"type\":\"deposit\",\"client\":1,\"tx\":4,\"amount\":\"1{}\"}}\n",
+        "0".repeat(8 * 1024)
+    );
+    let long = send(
+        addr,
+        &(long + "{\"type\":\"deposit\",\"client\":1,\"tx\":5,\"amount\":\"1\"}\n"),
+    )
+    .await;
+    assert_eq!(long.len(), 2);
+    assert!(long[0].starts_with(r#"{"line":1,"status":"rejected","reason":"malformed""#));
+    assert_eq!(long[1], r#"{"line":2,"status":"accepted"}"#);
+
+    stop.send(()).unwrap();
+    server.await.unwrap().unwrap();
+    let client = engine.lock().unwrap().client(1).unwrap();
+    let total = client.balances[&Currency::Usd].total;
+    assert_eq!(total.to_string(), "7.0000");
+}
+
+#[tokio::test]
+async fn rows_that_can_not_be_committed_are_acked_as_failed() {
+    let dir = std::env::temp_dir().join(format!("pinoedino-failed-{}", std::process::id()));
+    let _ = std::fs::remove_dir_all(&dir);
+    // Every commit checkpoints and fails once dir is removed
+    let store = crate::db::DurableDb::open(&dir, Default::default(), 0).unwrap();
+    std::fs::remove_dir_all(&dir).unwrap();
+    let engine = Arc::new(Mutex::new(Engine::with_store(store, Default::default())));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(serve(listener, engine.clone(), None, async {
+        stopped.await.unwrap();
+    }));
+
+    let acks = send(
+        addr,
+        "type,client,tx,amount\ndeposit,1,1,10\ndeposit,1,2,10\n",
+    )
+    .await;
+    assert_eq!(acks.len(), 1);
+    assert!(acks[0].starts_with(r#"{"line":2,"status":"failed","reason":"store_error""#));
+    // The store keeps failing
+    let acks = send(addr, "type,client,tx,amount\ndeposit,1,3,10\n").await;
+    assert!(acks[0].contains(r#""status":"failed""#));
+
+    stop.send(()).unwrap();
+    server.await.unwrap().unwrap();
+}
+
+// Send a request on a new connection and return status and body
+async fn request(addr: std::net::SocketAddr, request: &str) -> (u16, String) {
+    let mut stream = TcpStream::connect(addr).await.unwrap();
+    stream.write_all(request.as_bytes()).await.unwrap();
+    let mut response = String::new();
+    tokio::io::AsyncReadExt::read_to_string(&mut stream, &mut response)
+        .await
+        .unwrap();
+    let status = response[9..12].parse().unwrap();
+    let body = response[response.find("\r\n\r\n").unwrap() + 4..].to_string();
+    (status, body)
+}
+
+fn post(body: &str, content_type: &str) -> String {
+    format!(
+        "POST /transactions HTTP/1.1\r\nContent-Type: {}\r\nContent-Length: {}\r\n\r\n{}",
+        content_type,
+        body.len(),
+        body
+    )
+}
+
+#[tokio::test]
+async fn http_api() {
+    let engine = Arc::new(Mutex::new(Engine::new(
+        DisputePolicy::default(),
+        Default::default(),
+    )));
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let addr = listener.local_addr().unwrap();
+    let (stop, stopped) = tokio::sync::oneshot::channel::<()>();
+    let server = tokio::spawn(super::serve_http(listener, engine, 2, async {
+        stopped.await.unwrap();
+    }));
+
+    let deposit = r#"{"type":"deposit","client":7,"tx":1,"amount":"2.5","currency":"EUR"}"#;
+    assert_eq!(
+        request(addr, &post(deposit, "application/json")).await,
+        (200, r#"{"line":1,"status":"accepted"}"#.to_string())
+    );
+    let withdrawal = "type,client,tx,amount,currency\nwithdraw,7,2,5,EUR\n";
+    assert_eq!(
+        request(addr, &post(withdrawal, "text/csv")).await,
+        (
+            422,
+            r#"{"line":2,"status":"rejected","reason":"insufficient_funds","message":"insufficient funds"}"#
+                .to_string()
+        )
+    );
+    let expected = r#"[{"client":7,"currency":"EUR","available":"2.50","held":"0.00","total":"2.50","locked":false}]"#;
+    assert_eq!(
+        request(addr, "GET /clients/7 HTTP/1.1\r\n\r\n").await,
+        (200, expected.to_string())
+    );
+    assert_eq!(
+        request(addr, "GET /clients HTTP/1.1\r\n\r\n").await,
+        (200, expected.to_string())
+    );
+    assert_eq!(
+        request(addr, "GET /clients/8 HTTP/1.1\r\n\r\n").await.0,
+        404
+    );
+    assert_eq!(
+        request(addr, "DELETE /clients HTTP/1.1\r\n\r\n").await.0,
+        405
+    );
+    assert_eq!(
+        request(addr, &post("type,client,tx,amount\n", "text/csv"))
+            .await
+            .0,
+        400
+    );
+
+    stop.send(()).unwrap();
+    server.await.unwrap().unwrap();
+}
+
+#[tokio::test]
+async fn http_heads_are_capped() {
+    let padding = "a".repeat(9 * 1024);
+    let head = format!("GET /clients HTTP/1.1\r\nX-Padding: {}\r\n\r\n", padding);
+    let request = super::http::read_request(head.as_bytes()).await.unwrap();
+    assert_eq!(request.err().unwrap().status, 431);
+
+    let line = format!("GET /{} HTTP/1.1\r\n\r\n", padding);
+    let request = super::http::read_request(line.as_bytes()).await.unwrap();
+    assert_eq!(request.err().unwrap().status, 431);
+}